@@ -1,35 +1,339 @@
-use bdk::bitcoin::{Address, BlockHeader, Script, Transaction, Txid};
+use bdk::bitcoin::consensus::encode::VarInt;
+use bdk::bitcoin::secp256k1::Secp256k1;
+use bdk::bitcoin::util::address::Payload;
+use bdk::bitcoin::util::bip32::{ChildNumber, ExtendedPrivKey};
+use bdk::bitcoin::util::psbt::{self, PartiallySignedTransaction};
+use bdk::bitcoin::{
+    Address, BlockHash, BlockHeader, Network, OutPoint as BitcoinOutPoint, Script, Transaction,
+    Txid, WScriptHash,
+};
+#[cfg(any(feature = "esplora", feature = "esplora-reqwest"))]
+use bdk::blockchain::esplora::EsploraError;
 use bdk::blockchain::{noop_progress, Blockchain, IndexedChain, TxStatus};
 use bdk::database::BatchDatabase;
-use bdk::wallet::{AddressIndex, Wallet};
-use bdk::SignOptions;
+use bdk::descriptor::DescriptorSecretKey;
+use bdk::electrum_client;
+#[cfg(feature = "bip39")]
+use bdk::keys::bip39::Mnemonic;
+use bdk::wallet::{AddressIndex, AddressInfo, Wallet};
+use bdk::KeychainKind;
+use bdk::{FeeRate, LocalUtxo, SignOptions, TransactionDetails};
+
+use log::{debug, error, info, trace, warn};
+use serde::{Deserialize, Serialize};
 
 use lightning::chain::chaininterface::BroadcasterInterface;
-use lightning::chain::chaininterface::{ConfirmationTarget, FeeEstimator};
+use lightning::chain::chaininterface::{
+    ConfirmationTarget, FeeEstimator, FEERATE_FLOOR_SATS_PER_KW,
+};
+use lightning::chain::keysinterface::SpendableOutputDescriptor;
+use lightning::chain::transaction::OutPoint;
 use lightning::chain::WatchedOutput;
-use lightning::chain::{Confirm, Filter};
-use std::collections::HashMap;
+use lightning::chain::{BestBlock, Confirm, Filter};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::sync::Mutex;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+#[cfg(test)]
+mod mock;
 
 pub type TransactionWithHeight = (u32, Transaction);
 pub type TransactionWithPosition = (usize, Transaction);
 pub type TransactionWithHeightAndPosition = (u32, Transaction, usize);
+/// an external fee oracle returning a feerate in sats per 1000 weight
+/// for the given target, or None to fall back to the backend's estimate
+pub type FeeSource = Arc<dyn Fn(ConfirmationTarget) -> Option<u32> + Send + Sync>;
+/// looks up the status of many transactions at once, returning
+/// one status (None if unknown) per txid in the same order
+pub type BatchTxStatus = Box<dyn Fn(&[Txid]) -> Result<Vec<Option<TxStatus>>, bdk::Error> + Send>;
+/// looks up the history of many scripts at once, returning one history
+/// per script in the same order
+pub type BatchScriptHistory =
+    Box<dyn Fn(&[Script]) -> Result<Vec<Vec<(TxStatus, Transaction)>>, bdk::Error> + Send>;
+/// asks the backend whether the transaction would be accepted into
+/// its mempool without broadcasting it, e.g. bitcoind's testmempoolaccept
+pub type MempoolAccept = Box<dyn Fn(&Transaction) -> Result<bool, bdk::Error> + Send>;
+/// a utxo from outside your wallet: its outpoint, the psbt input
+/// describing it and the weight of the witness needed to spend it
+pub type ForeignUtxo = (BitcoinOutPoint, psbt::Input, usize);
+/// called with the txid and height of each watched transaction the
+/// first time a sync sees it confirmed
+pub type OnConfirmed = Arc<dyn Fn(Txid, u32) + Send + Sync>;
 
 #[derive(Debug)]
 pub enum Error {
     Bdk(bdk::Error),
+    /// the descriptor requires ldk's KeysManager to sign, use
+    /// KeysManager::spend_spendable_outputs to sweep it instead
+    UnsupportedSpendableOutput,
+    /// the wallet (or the selected utxos) can't cover the value plus fees
+    InsufficientFunds {
+        needed: u64,
+        available: u64,
+    },
+    /// the wallet descriptor does not contain an extended private key
+    MissingPrivateKey,
+    /// the address or wallet is for a different network than expected
+    NetworkMismatch {
+        expected: Network,
+        got: Network,
+    },
+    /// the value is below the dust threshold for the output script
+    DustValue {
+        value: u64,
+        dust_limit: u64,
+    },
+    /// the transaction has already confirmed so it can't be replaced
+    AlreadyConfirmed,
+    /// the transaction does not signal replace-by-fee (bip125)
+    NotReplaceable,
+    /// the transaction is not part of your onchain wallet
+    UnknownTransaction(Txid),
+    /// another sync is still running on this wallet
+    SyncInProgress,
+    /// the data is larger than the standard OP_RETURN output allows
+    DataTooLarge {
+        len: usize,
+        max: usize,
+    },
+    /// the outputs being swept are worth no more than the fee to sweep them
+    UneconomicalSweep {
+        value: u64,
+        fee: u64,
+    },
+    /// syncing the onchain bdk wallet failed
+    OnchainSync(bdk::Error),
+    /// providing chain data to ldk through Confirm failed
+    LightningSync(bdk::Error),
+    /// the requested height is above the current tip
+    HeightAboveTip {
+        height: u32,
+        tip: u32,
+    },
+    /// the funding script must appear in exactly one output of the
+    /// transaction for its outpoint to be handed to ldk
+    FundingOutputMismatch {
+        matches: usize,
+    },
+    /// the wallet's descriptor differs from the one it should replace
+    DescriptorMismatch,
+    /// the transaction is missing signatures, e.g. because the wallet
+    /// holds only some of the keys of a multisig descriptor
+    IncompleteSignature,
+    /// the backend doesn't support this operation
+    Unsupported,
+    /// a manually selected utxo has fewer confirmations than required
+    ShallowUtxo {
+        outpoint: BitcoinOutPoint,
+        min_confirmations: u32,
+    },
+    /// the task running the sync was cancelled, e.g. because the tokio
+    /// runtime is shutting down
+    SyncCancelled,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bdk(e) => write!(f, "bdk error: {}", e),
+            Self::UnsupportedSpendableOutput => {
+                write!(f, "spendable output must be swept with ldk's KeysManager")
+            }
+            Self::InsufficientFunds { needed, available } => write!(
+                f,
+                "insufficient funds: needed {} sats but only {} available",
+                needed, available
+            ),
+            Self::MissingPrivateKey => write!(f, "wallet has no extended private key"),
+            Self::NetworkMismatch { expected, got } => {
+                write!(f, "network mismatch: expected {} but got {}", expected, got)
+            }
+            Self::DustValue { value, dust_limit } => write!(
+                f,
+                "value of {} sats is below the dust limit of {} sats",
+                value, dust_limit
+            ),
+            Self::AlreadyConfirmed => write!(f, "transaction has already confirmed"),
+            Self::NotReplaceable => write!(f, "transaction does not signal replace-by-fee"),
+            Self::UnknownTransaction(txid) => {
+                write!(f, "transaction {} is not in the wallet", txid)
+            }
+            Self::SyncInProgress => write!(f, "a sync is already in progress"),
+            Self::DataTooLarge { len, max } => write!(
+                f,
+                "data of {} bytes exceeds the {} byte OP_RETURN limit",
+                len, max
+            ),
+            Self::UneconomicalSweep { value, fee } => write!(
+                f,
+                "sweeping {} sats is uneconomical with a fee of {} sats",
+                value, fee
+            ),
+            Self::OnchainSync(e) => write!(f, "onchain wallet sync failed: {}", e),
+            Self::LightningSync(e) => write!(f, "lightning sync failed: {}", e),
+            Self::HeightAboveTip { height, tip } => {
+                write!(f, "height {} is above the current tip {}", height, tip)
+            }
+            Self::FundingOutputMismatch { matches } => write!(
+                f,
+                "expected exactly one funding output but found {}",
+                matches
+            ),
+            Self::DescriptorMismatch => write!(f, "wallet descriptors do not match"),
+            Self::IncompleteSignature => write!(f, "transaction is missing signatures"),
+            Self::Unsupported => write!(f, "operation not supported by the backend"),
+            Self::ShallowUtxo {
+                outpoint,
+                min_confirmations,
+            } => write!(
+                f,
+                "utxo {} has fewer than {} confirmations",
+                outpoint, min_confirmations
+            ),
+            Self::SyncCancelled => write!(f, "the sync task was cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Bdk(e) | Self::OnchainSync(e) | Self::LightningSync(e) => Some(e),
+            _ => None,
+        }
+    }
 }
 
 impl From<bdk::Error> for Error {
     fn from(e: bdk::Error) -> Self {
-        Self::Bdk(e)
+        match e {
+            bdk::Error::InsufficientFunds { needed, available } => {
+                Self::InsufficientFunds { needed, available }
+            }
+            bdk::Error::TransactionConfirmed => Self::AlreadyConfirmed,
+            bdk::Error::IrreplaceableTransaction => Self::NotReplaceable,
+            e => Self::Bdk(e),
+        }
+    }
+}
+
+impl Error {
+    /// attributes a backend error to the lightning half of a sync
+    fn in_lightning_sync(self) -> Self {
+        match self {
+            Self::Bdk(e) => Self::LightningSync(e),
+            e => e,
+        }
+    }
+}
+
+/// locks the mutex, recovering the guard if another thread
+/// panicked while holding it instead of propagating the panic
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| {
+        warn!("recovering from a poisoned lock");
+        poisoned.into_inner()
+    })
+}
+
+/// marks a sync as in progress until dropped, so the flag is
+/// cleared however the sync finishes
+struct SyncGuard<'a>(&'a AtomicBool);
+
+impl Drop for SyncGuard<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+/// decides what happens when a backend query fails during sync. a strict
+/// sync aborts on the first error while a best-effort sync records it,
+/// skips whatever the query covered and carries on with everything else
+struct SyncErrors {
+    best_effort: bool,
+    errors: Vec<Error>,
+}
+
+impl SyncErrors {
+    fn new(best_effort: bool) -> Self {
+        Self {
+            best_effort,
+            errors: vec![],
+        }
+    }
+
+    /// returns None instead of the error when it should be skipped
+    fn check<T>(&mut self, result: Result<T, Error>) -> Result<Option<T>, Error> {
+        match result {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if self.best_effort => {
+                warn!("skipping after backend error during sync: {}", e);
+                self.errors.push(e);
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// a serializable snapshot of the transactions and outputs
+/// ldk has asked us to watch
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FilterState {
+    pub watched_transactions: Vec<(Txid, Script)>,
+    pub watched_outputs: Vec<WatchedOutputState>,
+    pub confirmed_txids: Vec<Txid>,
+    /// the txid and index of each watched output with a confirmed spend,
+    /// and the txid of the spending transaction
+    #[serde(default)]
+    pub confirmed_spends: Vec<(Txid, u16, Txid)>,
+}
+
+/// a serializable version of ldk's WatchedOutput
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WatchedOutputState {
+    pub block_hash: Option<BlockHash>,
+    pub txid: Txid,
+    pub index: u16,
+    pub script_pubkey: Script,
+}
+
+impl From<&WatchedOutput> for WatchedOutputState {
+    fn from(output: &WatchedOutput) -> Self {
+        Self {
+            block_hash: output.block_hash,
+            txid: output.outpoint.txid,
+            index: output.outpoint.index,
+            script_pubkey: output.script_pubkey.clone(),
+        }
+    }
+}
+
+impl From<WatchedOutputState> for WatchedOutput {
+    fn from(state: WatchedOutputState) -> Self {
+        Self {
+            block_hash: state.block_hash,
+            outpoint: OutPoint {
+                txid: state.txid,
+                index: state.index,
+            },
+            script_pubkey: state.script_pubkey,
+        }
     }
 }
 
 struct TxFilter {
     watched_transactions: Vec<(Txid, Script)>,
     watched_outputs: Vec<WatchedOutput>,
+    /// txids previously reported to ldk as confirmed so we can
+    /// tell ldk they were unconfirmed if they get reorged out
+    confirmed_txids: HashSet<Txid>,
+    /// the confirmed transaction spending each watched output so it
+    /// stops being checked once the output is forgotten
+    confirmed_spends: HashMap<OutPoint, Txid>,
 }
 
 impl TxFilter {
@@ -37,15 +341,122 @@ impl TxFilter {
         Self {
             watched_transactions: vec![],
             watched_outputs: vec![],
+            confirmed_txids: HashSet::new(),
+            confirmed_spends: HashMap::new(),
         }
     }
 
     fn register_tx(&mut self, txid: Txid, script: Script) {
-        self.watched_transactions.push((txid, script));
+        let watched = (txid, script);
+        if !self.watched_transactions.contains(&watched) {
+            self.watched_transactions.push(watched);
+        }
     }
 
     fn register_output(&mut self, output: WatchedOutput) {
-        self.watched_outputs.push(output);
+        let already_watched = self
+            .watched_outputs
+            .iter()
+            .any(|watched| watched.outpoint == output.outpoint);
+
+        if !already_watched {
+            self.watched_outputs.push(output);
+        }
+    }
+
+    fn forget_tx(&mut self, txid: &Txid) {
+        self.watched_transactions
+            .retain(|(watched_txid, _script)| watched_txid != txid);
+        self.confirmed_txids.remove(txid);
+    }
+
+    /// returns the txid of the output's confirmed spend if it is
+    /// no longer checked for unconfirmation
+    fn forget_output(&mut self, outpoint: &OutPoint) -> Option<Txid> {
+        self.watched_outputs
+            .retain(|output| &output.outpoint != outpoint);
+
+        let spend = self.confirmed_spends.remove(outpoint)?;
+        let still_watched = self
+            .watched_transactions
+            .iter()
+            .any(|(txid, _script)| *txid == spend)
+            || self.confirmed_spends.values().any(|txid| *txid == spend);
+        if still_watched {
+            return None;
+        }
+        self.confirmed_txids.remove(&spend);
+        Some(spend)
+    }
+
+    fn clear(&mut self) {
+        self.watched_transactions.clear();
+        self.watched_outputs.clear();
+        self.confirmed_txids.clear();
+        self.confirmed_spends.clear();
+    }
+
+    /// records a transaction reported to ldk as confirmed along with the
+    /// watched outputs it spends, returns false if it was already recorded
+    fn record_confirmed(&mut self, tx: &Transaction) -> bool {
+        let txid = tx.txid();
+        for output in &self.watched_outputs {
+            let outpoint = output.outpoint.into_bitcoin_outpoint();
+            if tx
+                .input
+                .iter()
+                .any(|input| input.previous_output == outpoint)
+            {
+                self.confirmed_spends.insert(output.outpoint, txid);
+            }
+        }
+        self.confirmed_txids.insert(txid)
+    }
+
+    fn forget_confirmed(&mut self, txid: &Txid) {
+        self.confirmed_txids.remove(txid);
+        self.confirmed_spends
+            .retain(|_outpoint, spend| spend != txid);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.watched_transactions.is_empty() && self.watched_outputs.is_empty()
+    }
+
+    fn export(&self) -> FilterState {
+        let mut confirmed_txids = self.confirmed_txids.iter().cloned().collect::<Vec<Txid>>();
+        confirmed_txids.sort_unstable();
+        let mut confirmed_spends = self
+            .confirmed_spends
+            .iter()
+            .map(|(outpoint, spend)| (outpoint.txid, outpoint.index, *spend))
+            .collect::<Vec<(Txid, u16, Txid)>>();
+        confirmed_spends.sort_unstable();
+
+        FilterState {
+            watched_transactions: self.watched_transactions.clone(),
+            watched_outputs: self
+                .watched_outputs
+                .iter()
+                .map(WatchedOutputState::from)
+                .collect(),
+            confirmed_txids,
+            confirmed_spends,
+        }
+    }
+
+    fn import(&mut self, state: FilterState) {
+        for (txid, script) in state.watched_transactions {
+            self.register_tx(txid, script);
+        }
+        for output in state.watched_outputs {
+            self.register_output(output.into());
+        }
+        self.confirmed_txids.extend(state.confirmed_txids);
+        for (txid, index, spend) in state.confirmed_spends {
+            self.confirmed_spends
+                .insert(OutPoint { txid, index }, spend);
+        }
     }
 }
 
@@ -55,320 +466,5451 @@ impl Default for TxFilter {
     }
 }
 
-/// Lightning Wallet
-///
-/// A wrapper around a bdk::Wallet to fulfill many of the requirements
-/// needed to use lightning with LDK.  Note: The bdk::Blockchain you use
-/// must implement the IndexedChain trait.
-pub struct LightningWallet<B, D> {
-    inner: Mutex<Wallet<B, D>>,
-    filter: Mutex<TxFilter>,
+/// number of blocks to target when estimating fees
+/// for each of ldk's ConfirmationTargets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeeTargets {
+    pub background: usize,
+    pub normal: usize,
+    pub high_priority: usize,
 }
 
-impl<B, D> LightningWallet<B, D>
-where
-    B: Blockchain + IndexedChain,
-    D: BatchDatabase,
-{
-    /// create a new lightning wallet from your bdk wallet
-    pub fn new(wallet: Wallet<B, D>) -> Self {
-        LightningWallet {
-            inner: Mutex::new(wallet),
-            filter: Mutex::new(TxFilter::new()),
+impl FeeTargets {
+    fn target_blocks(&self, confirmation_target: ConfirmationTarget) -> usize {
+        match confirmation_target {
+            ConfirmationTarget::Background => self.background,
+            ConfirmationTarget::Normal => self.normal,
+            ConfirmationTarget::HighPriority => self.high_priority,
         }
     }
+}
 
-    /// syncs both your onchain and lightning wallet to current tip
-    /// utilizes ldk's Confirm trait to provide chain data
-    pub fn sync(
-        &self,
-        channel_manager: Arc<dyn Confirm>,
-        chain_monitor: Arc<dyn Confirm>,
-    ) -> Result<(), Error> {
-        self.sync_onchain_wallet()?;
+impl Default for FeeTargets {
+    fn default() -> Self {
+        Self {
+            background: 6,
+            normal: 3,
+            high_priority: 1,
+        }
+    }
+}
 
-        let mut relevant_txids = channel_manager.get_relevant_txids();
-        relevant_txids.append(&mut chain_monitor.get_relevant_txids());
-        relevant_txids.sort_unstable();
-        relevant_txids.dedup();
+/// converts a bdk FeeRate into sats per 1000 weight units using bdk's
+/// weight based fee calculation, so fractional sats are rounded up
+/// rather than truncated
+fn fee_rate_to_sat_per_1000_weight(fee_rate: FeeRate) -> u32 {
+    fee_rate.fee_wu(1000) as u32
+}
 
-        let unconfirmed_txids = self.get_unconfirmed(relevant_txids)?;
-        for unconfirmed_txid in unconfirmed_txids {
-            channel_manager.transaction_unconfirmed(&unconfirmed_txid);
-            chain_monitor.transaction_unconfirmed(&unconfirmed_txid);
-        }
+/// finds the outpoint of the only output paying to the funding script
+fn find_funding_outpoint(tx: &Transaction, output_script: &Script) -> Result<OutPoint, Error> {
+    let vouts = tx
+        .output
+        .iter()
+        .enumerate()
+        .filter(|(_vout, output)| &output.script_pubkey == output_script)
+        .map(|(vout, _output)| vout)
+        .collect::<Vec<usize>>();
 
-        let confirmed_txs = self.get_confirmed_txs_by_block()?;
-        for (height, header, tx_list) in confirmed_txs {
-            let tx_list_ref = tx_list
+    match vouts.as_slice() {
+        [vout] => Ok(OutPoint {
+            txid: tx.txid(),
+            index: *vout as u16,
+        }),
+        _ => Err(Error::FundingOutputMismatch {
+            matches: vouts.len(),
+        }),
+    }
+}
+
+/// finds the outpoint of each of the funding outputs, in order. outputs
+/// paying the same script and value are matched to distinct vouts
+fn find_funding_outpoints(
+    tx: &Transaction,
+    outputs: &[(Script, u64)],
+) -> Result<Vec<OutPoint>, Error> {
+    let mut used_vouts = HashSet::new();
+    outputs
+        .iter()
+        .map(|(output_script, value)| {
+            let vout = tx
+                .output
                 .iter()
-                .map(|(height, tx)| (height.to_owned(), tx))
-                .collect::<Vec<(usize, &Transaction)>>();
+                .enumerate()
+                .position(|(vout, output)| {
+                    &output.script_pubkey == output_script
+                        && output.value == *value
+                        && !used_vouts.contains(&vout)
+                })
+                .ok_or(Error::FundingOutputMismatch { matches: 0 })?;
+            used_vouts.insert(vout);
 
-            channel_manager.transactions_confirmed(&header, tx_list_ref.as_slice(), height);
-            chain_monitor.transactions_confirmed(&header, tx_list_ref.as_slice(), height);
-        }
+            Ok(OutPoint {
+                txid: tx.txid(),
+                index: vout as u16,
+            })
+        })
+        .collect()
+}
 
-        let (tip_height, tip_header) = self.get_tip()?;
+/// bitcoin core's default minimum relay feerate (in sats per vbyte)
+const MIN_RELAY_FEE_SAT_PER_VB: f32 = 1.0;
 
-        channel_manager.best_block_updated(&tip_header, tip_height);
-        chain_monitor.best_block_updated(&tip_header, tip_height);
-        Ok(())
-    }
+/// feerate (in sats per vbyte) bitcoin core uses to decide what is dust
+const DUST_RELAY_FEE_SAT_PER_VB: u64 = 3;
 
-    /// returns the AddressIndex::LastUnused address for your wallet
-    /// this is useful when you need to sweep funds from a channel
-    /// back into your onchain wallet.
-    pub fn get_unused_address(&self) -> Result<Address, Error> {
-        let wallet = self.inner.lock().unwrap();
-        let address_info = wallet.get_address(AddressIndex::LastUnused)?;
-        Ok(address_info.address)
+/// lower bound on the size (in vbytes) of a funding transaction with a
+/// single p2wpkh input, a p2wsh funding output and a p2wpkh change output
+const MIN_FUNDING_TX_VBYTES: u64 = 11 + 68 + 43 + 31;
+
+/// returns Error::NetworkMismatch unless the address is for the network.
+/// testnet and regtest share base58 prefixes, so a legacy address parsed
+/// as testnet is accepted for regtest too, unlike bech32 tb1 addresses.
+/// signet shares both prefixes with testnet so any testnet address is
+/// accepted for signet
+fn check_address_network(address: &Address, network: Network) -> Result<(), Error> {
+    let is_legacy = !matches!(address.payload, Payload::WitnessProgram { .. });
+    let matches = address.network == network
+        || (address.network == Network::Testnet
+            && (network == Network::Signet || (is_legacy && network == Network::Regtest)));
+    if !matches {
+        return Err(Error::NetworkMismatch {
+            expected: network,
+            got: address.network,
+        });
     }
+    Ok(())
+}
 
-    /// when opening a channel you can use this to fund the channel
-    /// with the utxos in your bdk wallet
-    pub fn construct_funding_transaction(
-        &self,
-        output_script: &Script,
-        value: u64,
-        target_blocks: usize,
-    ) -> Result<Transaction, Error> {
-        let wallet = self.inner.lock().unwrap();
+/// gets an address from the given keychain of the wallet
+fn keychain_address<B, D: BatchDatabase>(
+    wallet: &Wallet<B, D>,
+    address_index: AddressIndex,
+    keychain: KeychainKind,
+) -> Result<AddressInfo, Error> {
+    let address_info = match keychain {
+        KeychainKind::External => wallet.get_address(address_index)?,
+        KeychainKind::Internal => wallet.get_internal_address(address_index)?,
+    };
+    Ok(address_info)
+}
 
-        let mut tx_builder = wallet.build_tx();
-        let fee_rate = wallet.client().estimate_fee(target_blocks)?;
+/// the height each of the wallet's transactions confirmed at, or None
+/// for unconfirmed ones
+fn confirmation_heights<B, D: BatchDatabase>(
+    wallet: &Wallet<B, D>,
+) -> Result<HashMap<Txid, Option<u32>>, Error> {
+    Ok(wallet
+        .list_transactions(false)?
+        .into_iter()
+        .map(|details| {
+            (
+                details.txid,
+                details.confirmation_time.map(|time| time.height),
+            )
+        })
+        .collect())
+}
 
-        tx_builder
-            .add_recipient(output_script.clone(), value)
-            .fee_rate(fee_rate)
-            .do_not_spend_change()
-            .enable_rbf();
+/// extracts the transaction from the psbt, returning
+/// Error::IncompleteSignature rather than a transaction whose inputs
+/// weren't all finalized when signing, which would fail to broadcast
+fn extract_finalized_tx(psbt: PartiallySignedTransaction) -> Result<Transaction, Error> {
+    let finalized = psbt
+        .inputs
+        .iter()
+        .all(|input| input.final_script_sig.is_some() || input.final_script_witness.is_some());
+    if !finalized {
+        return Err(Error::IncompleteSignature);
+    }
+    Ok(psbt.extract_tx())
+}
 
-        let (mut psbt, _tx_details) = tx_builder.finish()?;
+/// the value below which an output paying to this script is dust when
+/// relayed at fee_rate, following bitcoin core's GetDustThreshold: the
+/// fee to create the output and later spend it
+fn dust_limit_at(script: &Script, fee_rate: FeeRate) -> u64 {
+    if script.is_provably_unspendable() {
+        return 0;
+    }
 
-        let _finalized = wallet.sign(&mut psbt, SignOptions::default())?;
+    let output_size = 8 + VarInt(script.len() as u64).len() as u64 + script.len() as u64;
+    let spend_size = if script.is_witness_program() {
+        32 + 4 + 1 + (107 / 4) + 4
+    } else {
+        32 + 4 + 1 + 107 + 4
+    };
 
-        Ok(psbt.extract_tx())
-    }
+    (fee_rate.as_sat_vb() * (output_size + spend_size) as f32).floor() as u64
+}
 
-    fn sync_onchain_wallet(&self) -> Result<(), Error> {
-        let wallet = self.inner.lock().unwrap();
-        wallet.sync(noop_progress(), None)?;
-        Ok(())
+/// feerates to fall back to for each of ldk's ConfirmationTargets
+/// when the backend is unable to provide an estimate
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FallbackFeerates {
+    pub background: FeeRate,
+    pub normal: FeeRate,
+    pub high_priority: FeeRate,
+}
+
+impl FallbackFeerates {
+    fn sat_per_1000_weight(&self, confirmation_target: ConfirmationTarget) -> u32 {
+        let fee_rate = match confirmation_target {
+            ConfirmationTarget::Background => self.background,
+            ConfirmationTarget::Normal => self.normal,
+            ConfirmationTarget::HighPriority => self.high_priority,
+        };
+        fee_rate_to_sat_per_1000_weight(fee_rate)
     }
+}
 
-    fn get_unconfirmed(&self, txids: Vec<Txid>) -> Result<Vec<Txid>, Error> {
-        Ok(txids
-            .into_iter()
-            .map(|txid| self.augment_txid_with_confirmation_status(txid))
-            .collect::<Result<Vec<(Txid, bool)>, Error>>()?
-            .into_iter()
-            .filter(|(_txid, confirmed)| !confirmed)
-            .map(|(txid, _)| txid)
-            .collect())
+impl Default for FallbackFeerates {
+    fn default() -> Self {
+        Self {
+            background: FeeRate::from_sat_per_vb(FEERATE_FLOOR_SATS_PER_KW as f32 / 250.0),
+            normal: FeeRate::from_sat_per_vb(8.0),
+            high_priority: FeeRate::from_sat_per_vb(20.0),
+        }
     }
+}
 
-    fn get_confirmed_txs_by_block(
-        &self,
-    ) -> Result<Vec<(u32, BlockHeader, Vec<TransactionWithPosition>)>, Error> {
-        let mut txs_by_block: HashMap<u32, Vec<TransactionWithPosition>> = HashMap::new();
+/// weight of the witness needed to spend a p2wpkh output, which is
+/// what ldk's KeysManager uses for static outputs
+const P2WPKH_SATISFACTION_WEIGHT: usize = 4 + 1 + 73 + 34;
 
-        let filter = self.filter.lock().unwrap();
+/// size (in vbytes) of a transaction sweeping p2wpkh inputs to a single
+/// p2wpkh output, excluding the inputs
+const SWEEP_TX_BASE_VBYTES: u64 = 11 + 31;
 
-        let mut confirmed_txs = filter
-            .watched_transactions
-            .iter()
-            .map(|(txid, script)| self.get_confirmed_tx(txid, script))
-            .collect::<Result<Vec<Option<TransactionWithHeight>>, Error>>()?
-            .into_iter()
-            .flatten()
-            .collect::<Vec<TransactionWithHeight>>();
+/// size (in vbytes) of each p2wpkh input added to a sweep
+const SWEEP_INPUT_VBYTES: u64 = (41 * 4 + P2WPKH_SATISFACTION_WEIGHT as u64 + 3) / 4;
 
-        let mut confirmed_spent = filter
-            .watched_outputs
-            .iter()
-            .map(|output| self.get_confirmed_txs(output))
-            .collect::<Result<Vec<Vec<TransactionWithHeight>>, Error>>()?
-            .into_iter()
-            .flatten()
-            .collect::<Vec<TransactionWithHeight>>();
+/// hardened path, relative to the wallet's extended private key,
+/// used to derive the seed for ldk's KeysManager
+const LDK_SEED_DERIVATION_PATH: [u32; 2] = [535, 0];
 
-        confirmed_txs.append(&mut confirmed_spent);
+/// derives the 32 byte seed for ldk's KeysManager from an extended
+/// private key using the hardened path m/535h/0h
+fn derive_ldk_seed_from_xprv(xprv: &ExtendedPrivKey) -> Result<[u8; 32], Error> {
+    let secp = Secp256k1::new();
+    let path = LDK_SEED_DERIVATION_PATH
+        .iter()
+        .map(|index| ChildNumber::from_hardened_idx(*index))
+        .collect::<Result<Vec<ChildNumber>, _>>()
+        .map_err(|e| Error::Bdk(e.into()))?;
+    let derived = xprv
+        .derive_priv(&secp, &path)
+        .map_err(|e| Error::Bdk(e.into()))?;
 
-        let confirmed_txs_with_position = confirmed_txs
-            .into_iter()
-            .map(|(height, tx)| self.augment_with_position(height, tx))
-            .collect::<Result<Vec<Option<TransactionWithHeightAndPosition>>, Error>>()?
-            .into_iter()
-            .flatten()
-            .collect::<Vec<TransactionWithHeightAndPosition>>();
+    let mut seed = [0u8; 32];
+    seed.copy_from_slice(&derived.private_key.key[..]);
+    Ok(seed)
+}
 
-        for (height, tx, pos) in confirmed_txs_with_position {
-            txs_by_block.entry(height).or_default().push((pos, tx))
-        }
+/// derives the 32 byte seed for ldk's KeysManager from a bip39 mnemonic
+/// and optional passphrase, independent of any wallet: the mnemonic's
+/// bip39 seed is the bip32 master key and the ldk seed is its private key
+/// at m/535h/0h. this is the same seed derive_ldk_seed returns for a wallet
+/// whose descriptors use the master key of the same mnemonic, so the
+/// mnemonic alone restores both the onchain wallet and the ldk node
+#[cfg(feature = "bip39")]
+pub fn derive_ldk_seed_from_mnemonic(mnemonic: &Mnemonic, passphrase: Option<&str>) -> [u8; 32] {
+    let seed = mnemonic.to_seed(passphrase.unwrap_or(""));
+    // the network only changes how the key serializes, not what it derives
+    let xprv = ExtendedPrivKey::new_master(Network::Bitcoin, &seed)
+        .expect("a 64 byte seed is a valid master key");
+    derive_ldk_seed_from_xprv(&xprv).expect("hardened derivation from a valid key")
+}
 
-        txs_by_block
-            .into_iter()
-            .map(|(height, tx_list)| self.augment_with_header(height, tx_list))
-            .collect()
-    }
+/// how long a cached fee estimate is considered fresh by default
+const DEFAULT_FEE_CACHE_TTL: Duration = Duration::from_secs(60);
 
-    fn get_tip(&self) -> Result<(u32, BlockHeader), Error> {
-        let wallet = self.inner.lock().unwrap();
-        let tip_height = wallet.client().get_height()?;
-        let tip_header = wallet.client().get_header(tip_height)?;
-        Ok((tip_height, tip_header))
-    }
+/// how many scripts a batched script history lookup asks for at once
+const DEFAULT_SCRIPT_BATCH_SIZE: usize = 100;
 
-    fn augment_txid_with_confirmation_status(&self, txid: Txid) -> Result<(Txid, bool), Error> {
-        let wallet = self.inner.lock().unwrap();
-        wallet
-            .client()
-            .get_tx_status(&txid)
-            .map(|status| match status {
-                Some(status) => (txid, status.confirmed),
-                None => (txid, false),
-            })
-            .map_err(Error::Bdk)
+/// cache of recent fee estimates (in sats per 1000 weight)
+/// for each of ldk's ConfirmationTargets
+struct FeeCache {
+    ttl: Duration,
+    entries: [Option<(u32, Instant)>; 3],
+}
+
+impl FeeCache {
+    fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: [None; 3],
+        }
     }
 
-    fn get_confirmed_tx(
-        &self,
-        txid: &Txid,
-        script: &Script,
-    ) -> Result<Option<TransactionWithHeight>, Error> {
-        let wallet = self.inner.lock().unwrap();
-        wallet
-            .client()
-            .get_script_tx_history(script)
-            .map(|history| {
-                history
-                    .into_iter()
-                    .find(|(status, tx)| status.confirmed && tx.txid().eq(txid))
-                    .map(|(status, tx)| (status.block_height.unwrap(), tx))
-            })
-            .map_err(Error::Bdk)
+    fn index(confirmation_target: ConfirmationTarget) -> usize {
+        match confirmation_target {
+            ConfirmationTarget::Background => 0,
+            ConfirmationTarget::Normal => 1,
+            ConfirmationTarget::HighPriority => 2,
+        }
     }
 
-    fn get_confirmed_txs_from_script_history(
-        &self,
-        history: Vec<(TxStatus, Transaction)>,
-    ) -> Vec<TransactionWithHeight> {
-        history
-            .into_iter()
-            .filter(|(status, _tx)| status.confirmed)
-            .map(|(status, tx)| (status.block_height.unwrap(), tx))
-            .collect::<Vec<TransactionWithHeight>>()
+    fn get(&self, confirmation_target: ConfirmationTarget) -> Option<u32> {
+        self.entries[Self::index(confirmation_target)]
+            .filter(|(_, fetched_at)| fetched_at.elapsed() < self.ttl)
+            .map(|(sats_per_1000_weight, _)| sats_per_1000_weight)
     }
 
-    fn get_confirmed_txs(
-        &self,
-        output: &WatchedOutput,
-    ) -> Result<Vec<TransactionWithHeight>, Error> {
-        let wallet = self.inner.lock().unwrap();
+    fn insert(&mut self, confirmation_target: ConfirmationTarget, sats_per_1000_weight: u32) {
+        self.entries[Self::index(confirmation_target)] =
+            Some((sats_per_1000_weight, Instant::now()));
+    }
+}
 
-        wallet
-            .client()
-            .get_script_tx_history(&output.script_pubkey)
-            .map(|history| self.get_confirmed_txs_from_script_history(history))
-            .map_err(Error::Bdk)
+impl Default for FeeCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_FEE_CACHE_TTL)
     }
+}
 
-    fn augment_with_position(
-        &self,
-        height: u32,
-        tx: Transaction,
-    ) -> Result<Option<TransactionWithHeightAndPosition>, Error> {
-        let wallet = self.inner.lock().unwrap();
+/// a summary of the wallet's state for monitoring
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalletHealth {
+    /// height of the tip last reported to ldk, or the backend's tip
+    /// if lightning hasn't been synced yet
+    pub tip_height: u32,
+    /// sats in utxos that have confirmed
+    pub confirmed_balance: u64,
+    /// time since the onchain wallet last synced, None if it never has
+    pub last_sync_age: Option<Duration>,
+    /// number of transactions and outputs ldk has asked us to watch
+    pub watched_count: usize,
+}
 
-        wallet
-            .client()
-            .get_position_in_block(&tx.txid(), height as usize)
-            .map(|position| position.map(|pos| (height, tx, pos)))
-            .map_err(Error::Bdk)
-    }
+/// statistics about a single sync
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncStats {
+    /// number of relevant txids whose confirmation status was checked
+    pub checked: usize,
+    /// number of transactions reported to ldk as confirmed
+    pub confirmed: usize,
+    /// number of transactions reported to ldk as unconfirmed
+    pub unconfirmed: usize,
+    /// height of the tip reported to ldk
+    pub tip_height: u32,
+    /// how long the sync took
+    pub elapsed: Duration,
+}
 
-    fn augment_with_header(
-        &self,
-        height: u32,
-        tx_list: Vec<TransactionWithPosition>,
-    ) -> Result<(u32, BlockHeader, Vec<TransactionWithPosition>), Error> {
-        let wallet = self.inner.lock().unwrap();
-        wallet
-            .client()
-            .get_header(height)
-            .map(|header| (height, header, tx_list))
-            .map_err(Error::Bdk)
-    }
+/// how many times get_tip re-fetches the tip when it changes mid-call
+const MAX_TIP_ATTEMPTS: usize = 3;
+
+/// how backend calls made during sync are retried on transient errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryConfig {
+    /// total number of attempts, including the first, defaults to 3
+    pub max_attempts: u32,
+    /// delay before the first retry, doubling for every retry after
+    /// that, defaults to 100 milliseconds
+    pub base_delay: Duration,
 }
 
-impl<B, D> From<Wallet<B, D>> for LightningWallet<B, D>
-where
-    B: Blockchain + IndexedChain,
-    D: BatchDatabase,
-{
-    fn from(wallet: Wallet<B, D>) -> Self {
-        Self::new(wallet)
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(100),
+        }
     }
 }
 
-impl<B, D> FeeEstimator for LightningWallet<B, D>
-where
-    B: Blockchain + IndexedChain,
-    D: BatchDatabase,
-{
-    fn get_est_sat_per_1000_weight(&self, confirmation_target: ConfirmationTarget) -> u32 {
-        let wallet = self.inner.lock().unwrap();
+/// whether retrying the backend call that failed with this error could
+/// succeed. only connection and transport failures are retried, errors
+/// in a response or from the wallet itself won't fix themselves
+fn is_transient(e: &bdk::Error) -> bool {
+    match e {
+        bdk::Error::Electrum(e) => matches!(
+            e,
+            electrum_client::Error::IOError(_)
+                | electrum_client::Error::SharedIOError(_)
+                | electrum_client::Error::AllAttemptsErrored(_)
+        ),
+        #[cfg(any(feature = "esplora", feature = "esplora-reqwest"))]
+        bdk::Error::Esplora(e) => match &**e {
+            // other 4xx responses will be the same next time
+            EsploraError::HttpResponse(status) => *status == 429 || *status >= 500,
+            // bdk maps ureq's status errors to HttpResponse, what's left
+            // failed to connect or to read the response
+            #[cfg(feature = "esplora")]
+            EsploraError::Ureq(_) | EsploraError::UreqTransport(_) => true,
+            #[cfg(feature = "esplora-reqwest")]
+            EsploraError::Reqwest(e) => {
+                e.is_connect()
+                    || e.is_timeout()
+                    || e.status().map_or(false, |status| {
+                        status.as_u16() == 429 || status.is_server_error()
+                    })
+            }
+            EsploraError::Io(_) => true,
+            // not found answers and malformed responses won't change
+            _ => false,
+        },
+        _ => false,
+    }
+}
 
-        let target_blocks = match confirmation_target {
-            ConfirmationTarget::Background => 6,
-            ConfirmationTarget::Normal => 3,
-            ConfirmationTarget::HighPriority => 1,
-        };
+/// how the feerate for a funding transaction is chosen
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FundingFee {
+    /// estimate a feerate with the backend to confirm within this many blocks
+    TargetBlocks(usize),
+    /// use exactly this feerate
+    FeeRate(FeeRate),
+}
 
-        let estimate = wallet
-            .client()
-            .estimate_fee(target_blocks)
-            .unwrap_or_default();
-        let sats_per_vbyte = estimate.as_sat_vb() as u32;
-        sats_per_vbyte * 250
-    }
+/// the most data bitcoin core will relay in an OP_RETURN output
+pub const MAX_OP_RETURN_DATA_LEN: usize = 80;
+
+/// where the change output of a funding transaction goes
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChangePolicy {
+    /// pay change to the next address of the wallet's internal (change)
+    /// keychain, or the external keychain if it has no change descriptor
+    Internal,
+    /// pay change to this address, e.g. one held by another wallet
+    DrainTo(Address),
 }
 
-impl<B, D> BroadcasterInterface for LightningWallet<B, D>
-where
-    B: Blockchain + IndexedChain,
-    D: BatchDatabase,
-{
-    fn broadcast_transaction(&self, tx: &Transaction) {
-        let wallet = self.inner.lock().unwrap();
-        let _result = wallet.client().broadcast(tx);
+/// what a funding transaction pays to
+#[derive(Clone, Copy)]
+enum FundingOutputs<'a> {
+    /// each script gets its value, with change going back to the wallet
+    Recipients(&'a [(Script, u64)]),
+    /// everything that can be spent goes to the script, with no change
+    Drain(&'a Script),
+}
+
+/// options used when constructing a channel funding transaction
+#[derive(Debug, Clone, PartialEq)]
+pub struct FundingOptions {
+    /// how to pick the feerate, defaults to targeting confirmation in 6 blocks
+    pub fee: FundingFee,
+    /// whether the transaction signals replace-by-fee (bip125), defaults to true.
+    /// disable this for zero-conf channels whose peer won't accept a
+    /// funding transaction that could be replaced
+    pub rbf: bool,
+    /// when not empty only these utxos are spent, defaults to letting
+    /// bdk's coin selection choose from the whole wallet
+    pub utxos: Vec<BitcoinOutPoint>,
+    /// data to attach in an extra OP_RETURN output, at most
+    /// MAX_OP_RETURN_DATA_LEN bytes. defaults to no data output
+    pub data: Option<Vec<u8>>,
+    /// where new change is paid, defaults to the internal keychain. this
+    /// is separate from which utxos get spent: funding transactions never
+    /// spend existing change (bdk's do_not_spend_change) whatever the policy
+    pub change_policy: ChangePolicy,
+    /// only utxos with at least this many confirmations as of the current
+    /// tip are spent, so the funding can't be double spent out from under
+    /// the channel. defaults to 1, 0 also allows unconfirmed utxos
+    pub min_confirmations: u32,
+    /// utxos from outside your wallet that must be spent as well, e.g. for
+    /// a payjoin style or coordinated open. their owner has to sign the
+    /// resulting psbt so use construct_funding_psbt_with_foreign_utxos.
+    /// each psbt input needs the previous transaction (non_witness_utxo)
+    pub foreign_utxos: Vec<ForeignUtxo>,
+}
+
+impl Default for FundingOptions {
+    fn default() -> Self {
+        Self {
+            fee: FundingFee::TargetBlocks(6),
+            rbf: true,
+            utxos: vec![],
+            data: None,
+            change_policy: ChangePolicy::Internal,
+            min_confirmations: 1,
+            foreign_utxos: vec![],
+        }
     }
 }
 
-impl<B, D> Filter for LightningWallet<B, D>
+/// Lightning Wallet
+///
+/// A wrapper around a bdk::Wallet to fulfill many of the requirements
+/// needed to use lightning with LDK.  Note: The bdk::Blockchain you use
+/// must implement the IndexedChain trait.
+///
+/// All state is behind mutexes so a LightningWallet is Send + Sync (and can
+/// be shared with LDK through an Arc) whenever both the blockchain `B` and
+/// the database `D` are Send. Neither needs to be Sync.
+pub struct LightningWallet<B, D> {
+    /// every bdk::Wallet method we use takes &self, but the wallet keeps
+    /// its database in a RefCell so it is Send but not Sync. a RwLock
+    /// would require Sync to share read guards across threads, which
+    /// would stop this type being shareable with ldk, so a Mutex it is.
+    /// the lightning_wallet_is_send_and_sync test stops compiling if this
+    /// is swapped for a RwLock
+    inner: Mutex<Wallet<B, D>>,
+    filter: Mutex<TxFilter>,
+    fee_targets: Mutex<FeeTargets>,
+    fallback_feerates: Mutex<FallbackFeerates>,
+    fee_multiplier: Mutex<f64>,
+    fee_source: Mutex<Option<FeeSource>>,
+    fee_cache: Mutex<FeeCache>,
+    last_broadcast_error: Mutex<Option<(Txid, String)>>,
+    batch_tx_status: Mutex<Option<BatchTxStatus>>,
+    batch_script_history: Mutex<Option<BatchScriptHistory>>,
+    script_batch_size: Mutex<usize>,
+    syncing: AtomicBool,
+    locked_utxos: Mutex<HashSet<BitcoinOutPoint>>,
+    always_unspendable: Mutex<HashSet<BitcoinOutPoint>>,
+    retry_config: Mutex<RetryConfig>,
+    last_funding_feerate: Mutex<Option<FeeRate>>,
+    fee_overrides: Mutex<[Option<u32>; 3]>,
+    /// positions of confirmed transactions within their block, so
+    /// transactions that stay watched don't refetch the block every sync.
+    /// keyed by block hash so a reorg at the same height misses the cache
+    position_cache: Mutex<HashMap<(Txid, BlockHash), usize>>,
+    last_sync_time: Mutex<Option<Instant>>,
+    on_confirmed: Mutex<Option<OnConfirmed>>,
+    min_funding_feerate: Mutex<FeeRate>,
+    mempool_accept: Mutex<Option<MempoolAccept>>,
+    last_tip_height: Mutex<Option<u32>>,
+}
+
+impl<B, D> LightningWallet<B, D>
 where
     B: Blockchain + IndexedChain,
     D: BatchDatabase,
 {
-    fn register_tx(&self, txid: &Txid, script_pubkey: &Script) {
-        let mut filter = self.filter.lock().unwrap();
-        filter.register_tx(*txid, script_pubkey.clone());
+    /// create a new lightning wallet from your bdk wallet
+    pub fn new(wallet: Wallet<B, D>) -> Self {
+        Self::with_fee_targets(wallet, FeeTargets::default())
     }
 
-    fn register_output(&self, output: WatchedOutput) -> Option<TransactionWithPosition> {
-        let mut filter = self.filter.lock().unwrap();
-        filter.register_output(output);
-        // TODO: do we need to check for tx here or wait for next sync?
-        None
+    /// create a new lightning wallet from your bdk wallet, wrapped in an Arc.
+    /// ldk's FeeEstimator, BroadcasterInterface and Filter impls all take
+    /// &self so clones of this one Arc can be handed to ldk for all three
+    ///
+    /// ```ignore
+    /// let wallet = LightningWallet::new_arc(bdk_wallet);
+    /// let fee_estimator = Arc::clone(&wallet);
+    /// let broadcaster = Arc::clone(&wallet);
+    /// let filter = Arc::clone(&wallet);
+    /// ```
+    pub fn new_arc(wallet: Wallet<B, D>) -> Arc<Self> {
+        Arc::new(Self::new(wallet))
     }
-}
 
-#[cfg(test)]
-mod tests {
-    #[test]
-    fn it_works() {
-        let result = 2 + 2;
-        assert_eq!(result, 4);
+    /// create a new lightning wallet from your bdk wallet, checking that
+    /// it is on the same network as your ldk ChannelManager. a mainnet
+    /// node fed testnet chain data is the most common wiring mistake and
+    /// corrupts channel state silently, so prefer this over new
+    pub fn new_with_network(wallet: Wallet<B, D>, network: Network) -> Result<Self, Error> {
+        if wallet.network() != network {
+            return Err(Error::NetworkMismatch {
+                expected: network,
+                got: wallet.network(),
+            });
+        }
+        Ok(Self::new(wallet))
+    }
+
+    /// create a new lightning wallet from your bdk wallet that uses
+    /// the provided block targets when estimating fees for ldk
+    pub fn with_fee_targets(wallet: Wallet<B, D>, fee_targets: FeeTargets) -> Self {
+        LightningWallet {
+            inner: Mutex::new(wallet),
+            filter: Mutex::new(TxFilter::new()),
+            fee_targets: Mutex::new(fee_targets),
+            fallback_feerates: Mutex::new(FallbackFeerates::default()),
+            fee_multiplier: Mutex::new(1.0),
+            fee_source: Mutex::new(None),
+            fee_cache: Mutex::new(FeeCache::default()),
+            last_broadcast_error: Mutex::new(None),
+            batch_tx_status: Mutex::new(None),
+            batch_script_history: Mutex::new(None),
+            script_batch_size: Mutex::new(DEFAULT_SCRIPT_BATCH_SIZE),
+            syncing: AtomicBool::new(false),
+            locked_utxos: Mutex::new(HashSet::new()),
+            always_unspendable: Mutex::new(HashSet::new()),
+            retry_config: Mutex::new(RetryConfig::default()),
+            last_funding_feerate: Mutex::new(None),
+            fee_overrides: Mutex::new([None; 3]),
+            position_cache: Mutex::new(HashMap::new()),
+            last_sync_time: Mutex::new(None),
+            on_confirmed: Mutex::new(None),
+            min_funding_feerate: Mutex::new(FeeRate::from_sat_per_vb(MIN_RELAY_FEE_SAT_PER_VB)),
+            mempool_accept: Mutex::new(None),
+            last_tip_height: Mutex::new(None),
+        }
+    }
+
+    /// update the block targets used when estimating fees for ldk
+    /// this invalidates any cached fee estimates
+    pub fn set_fee_targets(&self, fee_targets: FeeTargets) {
+        let mut current = lock(&self.fee_targets);
+        *current = fee_targets;
+
+        let mut fee_cache = lock(&self.fee_cache);
+        *fee_cache = FeeCache::new(fee_cache.ttl);
+    }
+
+    /// update the feerates ldk is given when the backend
+    /// is unable to provide a fee estimate
+    pub fn set_fallback_feerates(&self, fallback_feerates: FallbackFeerates) {
+        let mut current = lock(&self.fallback_feerates);
+        *current = fallback_feerates;
+    }
+
+    /// scale fee estimates before handing them to ldk
+    /// e.g. 1.25 to pay a quarter more than estimated (defaults to 1.0)
+    /// fallback feerates are used as configured and are not scaled
+    pub fn set_fee_multiplier(&self, fee_multiplier: f64) {
+        let mut current = lock(&self.fee_multiplier);
+        *current = fee_multiplier;
+    }
+
+    /// consult your own fee oracle (e.g. mempool.space) before the backend
+    /// when estimating fees for ldk. the backend, and then the fallback
+    /// feerates, are only used when the oracle returns None. oracle
+    /// estimates are scaled by the fee multiplier like the backend's
+    pub fn set_fee_source(&self, fee_source: FeeSource) {
+        let mut current = lock(&self.fee_source);
+        *current = Some(fee_source);
+    }
+
+    /// react to watched transactions (e.g. a channel funding or sweep)
+    /// confirming instead of polling for them. the callback is called once
+    /// per transaction, when a sync first reports it to ldk as confirmed,
+    /// and again only if it is reorged out and confirms a second time.
+    /// it runs on the thread doing the sync, after the wallet's locks are
+    /// released, so it must not block but may call back into the wallet
+    pub fn set_on_confirmed(&self, on_confirmed: OnConfirmed) {
+        let mut current = lock(&self.on_confirmed);
+        *current = Some(on_confirmed);
+    }
+
+    /// update how backend calls made during sync are retried
+    pub fn set_retry_config(&self, retry_config: RetryConfig) {
+        let mut current = lock(&self.retry_config);
+        *current = retry_config;
+    }
+
+    /// pins the feerate (in sats per 1000 weight) given to ldk for the
+    /// target, bypassing every other fee source. mostly useful for
+    /// deterministic tests of channel flows
+    pub fn override_feerate(&self, confirmation_target: ConfirmationTarget, sat_per_kw: u32) {
+        let mut fee_overrides = lock(&self.fee_overrides);
+        fee_overrides[FeeCache::index(confirmation_target)] = Some(sat_per_kw);
+    }
+
+    /// removes all feerates pinned with override_feerate
+    pub fn clear_overrides(&self) {
+        let mut fee_overrides = lock(&self.fee_overrides);
+        *fee_overrides = [None; 3];
+    }
+
+    /// update how long fee estimates are cached before
+    /// the backend is queried again (defaults to 60 seconds)
+    pub fn set_fee_cache_ttl(&self, ttl: Duration) {
+        let mut fee_cache = lock(&self.fee_cache);
+        fee_cache.ttl = ttl;
+    }
+
+    /// forces the cached fee estimates for all of ldk's
+    /// ConfirmationTargets to be refreshed from the backend
+    pub fn refresh_fee_cache(&self) -> Result<(), Error> {
+        for confirmation_target in [
+            ConfirmationTarget::Background,
+            ConfirmationTarget::Normal,
+            ConfirmationTarget::HighPriority,
+        ] {
+            let sats_per_1000_weight = self.estimate_sat_per_1000_weight(confirmation_target)?;
+            let mut fee_cache = lock(&self.fee_cache);
+            fee_cache.insert(confirmation_target, sats_per_1000_weight);
+        }
+        Ok(())
+    }
+
+    /// syncs both your onchain and lightning wallet to current tip
+    /// utilizes ldk's Confirm trait to provide chain data
+    ///
+    /// backend errors are returned as Error::OnchainSync or
+    /// Error::LightningSync depending on which half of the sync failed
+    pub fn sync(
+        &self,
+        channel_manager: Arc<dyn Confirm>,
+        chain_monitor: Arc<dyn Confirm>,
+    ) -> Result<(), Error> {
+        self.sync_with_stats(channel_manager, chain_monitor)
+            .map(|_stats| ())
+    }
+
+    /// same as sync but returns statistics about what happened
+    ///
+    /// only one sync (including sync_lightning_only) runs at a time, an
+    /// overlapping call returns Error::SyncInProgress straight away
+    /// instead of waiting, since the running sync already brings ldk up
+    /// to date and a second one would only repeat its notifications
+    pub fn sync_with_stats(
+        &self,
+        channel_manager: Arc<dyn Confirm>,
+        chain_monitor: Arc<dyn Confirm>,
+    ) -> Result<SyncStats, Error> {
+        let _guard = self.start_sync()?;
+        let started_at = Instant::now();
+        self.sync_onchain_wallet()?;
+        let mut errors = SyncErrors::new(false);
+        let stats = self.sync_lightning(channel_manager, chain_monitor, None, &mut errors)?;
+        Ok(SyncStats {
+            elapsed: started_at.elapsed(),
+            ..stats
+        })
+    }
+
+    /// same as sync_with_stats but a failed backend query (e.g. one
+    /// script's history timing out) only skips the transactions it
+    /// covers instead of aborting the whole sync. the skipped errors
+    /// are returned alongside the stats. telling ldk about the new tip
+    /// counts as progress even when every other query failed, so Err is
+    /// only returned when the tip itself couldn't be fetched.
+    ///
+    /// anything skipped is picked up by the next sync, and ldk's Confirm
+    /// handles being told about the same confirmation more than once, so
+    /// confirmations are delivered at least once rather than exactly once
+    pub fn sync_best_effort(
+        &self,
+        channel_manager: Arc<dyn Confirm>,
+        chain_monitor: Arc<dyn Confirm>,
+    ) -> Result<(SyncStats, Vec<Error>), Error> {
+        let _guard = self.start_sync()?;
+        let started_at = Instant::now();
+        let mut errors = SyncErrors::new(true);
+        errors.check(self.sync_onchain_wallet())?;
+        let stats = self.sync_lightning(channel_manager, chain_monitor, None, &mut errors)?;
+
+        let stats = SyncStats {
+            elapsed: started_at.elapsed(),
+            ..stats
+        };
+        Ok((stats, errors.errors))
+    }
+
+    /// provides chain data to ldk's Confirm trait without first syncing
+    /// your onchain wallet. useful if you sync the onchain wallet with
+    /// sync_onchain_wallet on a separate (likely slower) schedule
+    pub fn sync_lightning_only(
+        &self,
+        channel_manager: Arc<dyn Confirm>,
+        chain_monitor: Arc<dyn Confirm>,
+    ) -> Result<(), Error> {
+        let _guard = self.start_sync()?;
+        self.sync_lightning(
+            channel_manager,
+            chain_monitor,
+            None,
+            &mut SyncErrors::new(false),
+        )
+        .map(|_stats| ())
+    }
+
+    /// provides chain data to ldk's Confirm trait as if target_height
+    /// were the tip, so confirmations can be replayed deterministically.
+    /// transactions confirmed above target_height are left out and
+    /// best_block_updated is given target_height's header.
+    ///
+    /// like sync_lightning_only this does not sync your onchain wallet,
+    /// since bdk always syncs it to the current tip
+    pub fn sync_to_height(
+        &self,
+        channel_manager: Arc<dyn Confirm>,
+        chain_monitor: Arc<dyn Confirm>,
+        target_height: u32,
+    ) -> Result<SyncStats, Error> {
+        let _guard = self.start_sync()?;
+        self.sync_lightning(
+            channel_manager,
+            chain_monitor,
+            Some(target_height),
+            &mut SyncErrors::new(false),
+        )
+    }
+
+    fn start_sync(&self) -> Result<SyncGuard<'_>, Error> {
+        self.syncing
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .map_err(|_| Error::SyncInProgress)?;
+        Ok(SyncGuard(&self.syncing))
+    }
+
+    /// use a batched lookup when checking which of ldk's relevant txids
+    /// are still confirmed during sync, instead of one get_tx_status
+    /// request per txid. IndexedChain has no batch method so this must
+    /// be provided by the caller, e.g. using electrum's batch requests
+    pub fn set_batch_tx_status(&self, batch_tx_status: BatchTxStatus) {
+        let mut current = lock(&self.batch_tx_status);
+        *current = Some(batch_tx_status);
+    }
+
+    /// use batched lookups of the watched scripts' histories during sync,
+    /// instead of one get_script_tx_history request per script. the bdk
+    /// Wallet isn't Sync so the requests can't be spread over threads, and
+    /// IndexedChain has no batch method so this must be provided by the
+    /// caller, e.g. using electrum's batch_script_get_history
+    pub fn set_batch_script_history(&self, batch_script_history: BatchScriptHistory) {
+        let mut current = lock(&self.batch_script_history);
+        *current = Some(batch_script_history);
+    }
+
+    /// the most scripts handed to the batched script history lookup at
+    /// once, bounding how many requests the backend works through in
+    /// parallel. defaults to 100
+    pub fn set_script_batch_size(&self, script_batch_size: usize) {
+        let mut current = lock(&self.script_batch_size);
+        *current = script_batch_size.max(1);
+    }
+
+    /// lets test_mempool_accept check transactions with the backend.
+    /// neither bdk's Blockchain nor IndexedChain can test mempool
+    /// acceptance so this must be provided by the caller, e.g. using
+    /// bitcoind's testmempoolaccept rpc
+    pub fn set_mempool_accept(&self, mempool_accept: MempoolAccept) {
+        let mut current = lock(&self.mempool_accept);
+        *current = Some(mempool_accept);
+    }
+
+    /// returns whether the backend would accept the transaction into its
+    /// mempool, e.g. to check a channel funding transaction isn't
+    /// non-standard or paying too little before opening the channel.
+    /// returns Error::Unsupported unless set_mempool_accept was called
+    pub fn test_mempool_accept(&self, tx: &Transaction) -> Result<bool, Error> {
+        match lock(&self.mempool_accept).as_ref() {
+            Some(mempool_accept) => mempool_accept(tx).map_err(Error::Bdk),
+            None => Err(Error::Unsupported),
+        }
+    }
+
+    /// watches the output like Filter::register_output and immediately
+    /// checks whether it was already spent, returning the confirmed spend
+    /// with its height and position. unlike Filter::register_output a
+    /// failed lookup is returned as an error rather than treated as
+    /// unspent, which makes catching up after downtime easier to reason about
+    pub fn register_and_scan_output(
+        &self,
+        output: WatchedOutput,
+    ) -> Result<Option<TransactionWithHeightAndPosition>, Error> {
+        lock(&self.filter).register_output(output.clone());
+        self.get_confirmed_spend(&output)
+    }
+
+    /// stop watching a transaction registered by ldk
+    /// callers should forget a transaction once the chain monitor
+    /// reports it as permanently resolved so sync no longer scans it
+    pub fn forget_tx(&self, txid: &Txid) {
+        let mut filter = lock(&self.filter);
+        filter.forget_tx(txid);
+        drop(filter);
+
+        self.forget_positions(txid);
+    }
+
+    /// stop watching an output registered by ldk
+    /// callers should forget an output once the chain monitor
+    /// reports it as permanently resolved so sync no longer scans it
+    pub fn forget_output(&self, outpoint: &OutPoint) {
+        let mut filter = lock(&self.filter);
+        let forgotten_spend = filter.forget_output(outpoint);
+        drop(filter);
+
+        if let Some(txid) = forgotten_spend {
+            self.forget_positions(&txid);
+        }
+    }
+
+    /// stop watching all transactions and outputs registered by ldk
+    pub fn clear_filter(&self) {
+        let mut filter = lock(&self.filter);
+        filter.clear();
+        drop(filter);
+
+        lock(&self.position_cache).clear();
+    }
+
+    /// the transactions ldk has asked us to watch, with the script
+    /// used to look up their confirmation
+    pub fn watched_transactions(&self) -> Vec<(Txid, Script)> {
+        lock(&self.filter).watched_transactions.clone()
+    }
+
+    /// the outputs ldk has asked us to watch for spends
+    pub fn watched_outputs(&self) -> Vec<OutPoint> {
+        lock(&self.filter)
+            .watched_outputs
+            .iter()
+            .map(|output| output.outpoint)
+            .collect()
+    }
+
+    /// returns a snapshot of everything ldk has asked us to watch so it
+    /// can be persisted alongside your channel monitors
+    pub fn export_filter(&self) -> FilterState {
+        let filter = lock(&self.filter);
+        filter.export()
+    }
+
+    /// restores a previously exported filter, merging it with anything
+    /// already registered. import it before the first sync after a restart
+    /// so confirmations that happened while offline are delivered to ldk
+    /// through Confirm on that sync rather than waiting for ldk to
+    /// re-register everything.
+    pub fn import_filter(&self, state: FilterState) {
+        let mut filter = lock(&self.filter);
+        filter.import(state);
+    }
+
+    /// broadcasts each of the transactions in order, logging any that fail
+    /// this mirrors the batch broadcast interface of newer ldk versions
+    /// so packages (e.g. a parent and its cpfp child) go out together
+    pub fn broadcast_transactions(&self, txs: &[&Transaction]) {
+        let wallet = lock(&self.inner);
+        for tx in txs {
+            if let Err(e) = wallet.client().broadcast(tx) {
+                let txid = tx.txid();
+                error!("failed to broadcast transaction {}: {}", txid, e);
+                *lock(&self.last_broadcast_error) = Some((txid, e.to_string()));
+            }
+        }
+    }
+
+    /// rebroadcasts every unconfirmed transaction in your onchain wallet
+    /// and returns how many were rebroadcast. broadcasts can be dropped
+    /// by peers so callers should invoke this on a timer alongside sync
+    /// to make sure things like force-close sweeps actually propagate.
+    /// transactions that confirmed since the last sync are skipped.
+    pub fn rebroadcast_unconfirmed(&self) -> Result<usize, Error> {
+        let transactions = lock(&self.inner).list_transactions(true)?;
+        let mut unconfirmed = vec![];
+        for details in transactions {
+            if details.confirmation_time.is_some() || self.is_confirmed(&details.txid)? {
+                continue;
+            }
+            unconfirmed.extend(details.transaction);
+        }
+
+        let txs = unconfirmed.iter().collect::<Vec<&Transaction>>();
+        self.broadcast_transactions(&txs);
+        Ok(txs.len())
+    }
+
+    /// returns the txid and error message of the most recent
+    /// broadcast that failed, if any
+    pub fn last_broadcast_error(&self) -> Option<(Txid, String)> {
+        lock(&self.last_broadcast_error).clone()
+    }
+
+    /// runs the closure with the wrapped bdk wallet for anything not
+    /// exposed here (psbt co-signing, descriptor inspection, etc.)
+    /// the wallet stays locked for the duration of the closure so it
+    /// must not call back into this LightningWallet or it will deadlock
+    ///
+    /// ```ignore
+    /// let is_mine = lightning_wallet.with_wallet(|wallet| wallet.is_mine(&script))?;
+    /// ```
+    pub fn with_wallet<R>(&self, f: impl FnOnce(&Wallet<B, D>) -> R) -> R {
+        let wallet = lock(&self.inner);
+        f(&wallet)
+    }
+
+    /// returns the network your onchain wallet is on
+    pub fn network(&self) -> Network {
+        let wallet = lock(&self.inner);
+        wallet.network()
+    }
+
+    /// returns the balance (in sats) of your onchain wallet as of the last sync
+    /// this is useful to check there are enough funds before
+    /// constructing a channel funding transaction
+    ///
+    /// note: the bdk version in use reports a single total rather than
+    /// a confirmed/pending breakdown. it needs the wallet lock so it
+    /// waits for an onchain sync in progress, see sync_onchain_wallet
+    pub fn get_balance(&self) -> Result<u64, Error> {
+        let wallet = lock(&self.inner);
+        Ok(wallet.get_balance()?)
+    }
+
+    /// returns the transaction history of your onchain wallet as of the
+    /// last sync, including the confirmation height and timestamp of
+    /// each confirmed transaction. set include_raw to also get the
+    /// full transactions.
+    pub fn list_transactions(&self, include_raw: bool) -> Result<Vec<TransactionDetails>, Error> {
+        let wallet = lock(&self.inner);
+        Ok(wallet.list_transactions(include_raw)?)
+    }
+
+    /// returns the unspent outputs in your onchain wallet as of the last
+    /// sync. useful for deciding which utxos should fund a channel.
+    pub fn list_unspent(&self) -> Result<Vec<LocalUtxo>, Error> {
+        let wallet = lock(&self.inner);
+        Ok(wallet.list_unspent()?)
+    }
+
+    /// replaces your bdk wallet, e.g. with one using a different electrum
+    /// or esplora server when yours goes down, and returns the old one.
+    /// bdk has no way to swap only the blockchain client so build a new
+    /// wallet with the same descriptors, network and (ideally) database.
+    ///
+    /// the wallet is swapped under the same lock every other method uses
+    /// so it is safe to call from any thread, but it returns
+    /// Error::SyncInProgress rather than swapping the backend out from
+    /// under a running sync, so retry once that sync has completed
+    pub fn swap_wallet(&self, wallet: Wallet<B, D>) -> Result<Wallet<B, D>, Error> {
+        let _guard = self.start_sync()?;
+        let mut current = lock(&self.inner);
+
+        if wallet.network() != current.network() {
+            return Err(Error::NetworkMismatch {
+                expected: current.network(),
+                got: wallet.network(),
+            });
+        }
+        for keychain in [KeychainKind::External, KeychainKind::Internal] {
+            if wallet.public_descriptor(keychain)? != current.public_descriptor(keychain)? {
+                return Err(Error::DescriptorMismatch);
+            }
+        }
+
+        info!("swapping the onchain wallet");
+        Ok(std::mem::replace(&mut *current, wallet))
+    }
+
+    /// the value below which an output paying to the script is dust when
+    /// relayed at fee_rate, e.g. 294 sats for p2wpkh or 330 sats for p2tr
+    /// at bitcoin core's default dust relay feerate of 3 sats per vbyte
+    pub fn dust_limit(&self, script: &Script, fee_rate: FeeRate) -> u64 {
+        dust_limit_at(script, fee_rate)
+    }
+
+    /// returns your external descriptor with any private keys replaced by
+    /// their public keys, so operators can check on startup that the
+    /// wallet matches their backup before feeding chain data to ldk
+    pub fn public_descriptor(&self) -> Result<Option<String>, Error> {
+        let wallet = lock(&self.inner);
+        let descriptor = wallet.public_descriptor(KeychainKind::External)?;
+        Ok(descriptor.map(|descriptor| descriptor.to_string()))
+    }
+
+    /// deterministically derives the 32 byte seed for ldk's KeysManager
+    /// from the extended private key backing your external descriptor,
+    /// so the lightning node key can be restored from the same backup.
+    /// the seed is the private key at the hardened path m/535h/0h
+    /// relative to that extended private key.
+    pub fn derive_ldk_seed(&self) -> Result<[u8; 32], Error> {
+        let wallet = lock(&self.inner);
+        let signers = wallet.get_signers(KeychainKind::External);
+
+        let xprv = signers
+            .signers()
+            .into_iter()
+            .find_map(|signer| match signer.descriptor_secret_key() {
+                Some(DescriptorSecretKey::XPrv(xkey)) => Some(xkey.xkey),
+                _ => None,
+            })
+            .ok_or(Error::MissingPrivateKey)?;
+
+        derive_ldk_seed_from_xprv(&xprv)
+    }
+
+    /// returns the AddressIndex::LastUnused address for your wallet
+    /// this is useful when you need to sweep funds from a channel
+    /// back into your onchain wallet.
+    pub fn get_unused_address(&self) -> Result<Address, Error> {
+        self.get_unused_address_from(KeychainKind::External)
+    }
+
+    /// same as get_unused_address but from the given keychain, e.g. the
+    /// internal (change) keychain to keep external addresses for invoicing
+    pub fn get_unused_address_from(&self, keychain: KeychainKind) -> Result<Address, Error> {
+        let address_info = self.get_keychain_address(AddressIndex::LastUnused, keychain)?;
+        Ok(address_info.address)
+    }
+
+    /// returns an address (and its derivation index) from your wallet
+    /// AddressIndex::LastUnused will hand out the same address until it
+    /// receives funds, so use AddressIndex::New when sweeping multiple
+    /// channel closes if you want to avoid linking them onchain
+    pub fn get_address(&self, address_index: AddressIndex) -> Result<AddressInfo, Error> {
+        self.get_keychain_address(address_index, KeychainKind::External)
+    }
+
+    /// same as get_address but from the given keychain. wallets without a
+    /// change descriptor hand out external addresses for both keychains
+    pub fn get_keychain_address(
+        &self,
+        address_index: AddressIndex,
+        keychain: KeychainKind,
+    ) -> Result<AddressInfo, Error> {
+        let wallet = lock(&self.inner);
+        keychain_address(&wallet, address_index, keychain)
+    }
+
+    /// when opening a channel you can use this to fund the channel
+    /// with the utxos in your bdk wallet
+    pub fn construct_funding_transaction(
+        &self,
+        output_script: &Script,
+        value: u64,
+        target_blocks: usize,
+    ) -> Result<Transaction, Error> {
+        let options = FundingOptions {
+            fee: FundingFee::TargetBlocks(target_blocks),
+            ..Default::default()
+        };
+        self.construct_funding_transaction_with_options(output_script, value, &options)
+    }
+
+    /// same as construct_funding_transaction but also returns the funding
+    /// outpoint, so you don't have to find the funding output's vout
+    /// yourself before handing it to ldk
+    pub fn create_channel_funding(
+        &self,
+        output_script: &Script,
+        value: u64,
+        target_blocks: usize,
+    ) -> Result<(Transaction, OutPoint), Error> {
+        let tx = self.construct_funding_transaction(output_script, value, target_blocks)?;
+        let funding_outpoint = find_funding_outpoint(&tx, output_script)?;
+        Ok((tx, funding_outpoint))
+    }
+
+    /// same as construct_funding_transaction but lets you choose whether the
+    /// funding transaction signals replace-by-fee (bip125). some peers won't
+    /// accept a zero-conf channel whose funding transaction could be replaced
+    /// so disable rbf when opening those.
+    pub fn construct_funding_transaction_with_rbf(
+        &self,
+        output_script: &Script,
+        value: u64,
+        target_blocks: usize,
+        rbf: bool,
+    ) -> Result<Transaction, Error> {
+        let options = FundingOptions {
+            fee: FundingFee::TargetBlocks(target_blocks),
+            rbf,
+            ..Default::default()
+        };
+        self.construct_funding_transaction_with_options(output_script, value, &options)
+    }
+
+    /// same as construct_funding_transaction but uses the provided
+    /// feerate instead of estimating one with the backend
+    pub fn construct_funding_transaction_with_feerate(
+        &self,
+        output_script: &Script,
+        value: u64,
+        fee_rate: FeeRate,
+    ) -> Result<Transaction, Error> {
+        let (psbt, _tx_details) = self.construct_funding_psbt(output_script, value, fee_rate)?;
+        extract_finalized_tx(psbt)
+    }
+
+    /// reserves a utxo so coin selection for funding transactions skips it,
+    /// e.g. while another channel open spending it has yet to be broadcast.
+    /// locks are released once an onchain sync sees the utxo spent
+    pub fn lock_utxo(&self, outpoint: BitcoinOutPoint) {
+        lock(&self.locked_utxos).insert(outpoint);
+    }
+
+    /// releases a utxo reserved with lock_utxo
+    pub fn unlock_utxo(&self, outpoint: &BitcoinOutPoint) {
+        lock(&self.locked_utxos).remove(outpoint);
+    }
+
+    /// permanently excludes these utxos from every transaction this wallet
+    /// builds, e.g. a coin kept aside for bumping anchor outputs, replacing
+    /// any previously excluded. unlike lock_utxo these are never released
+    /// by sync, only by calling this again without them
+    pub fn set_always_unspendable(&self, outpoints: &[BitcoinOutPoint]) {
+        let mut always_unspendable = lock(&self.always_unspendable);
+        *always_unspendable = outpoints.iter().cloned().collect();
+    }
+
+    /// utxos coin selection must skip, both locked and always unspendable
+    fn unspendable_utxos(&self) -> Vec<BitcoinOutPoint> {
+        let mut unspendable = lock(&self.locked_utxos).iter().cloned().collect::<Vec<_>>();
+        unspendable.extend(lock(&self.always_unspendable).iter());
+        unspendable
+    }
+
+    /// update the lowest feerate funding transactions pay when the backend
+    /// estimates their feerate (defaults to 1 sat/vbyte, the minimum relay
+    /// feerate), so an unreasonably low estimate can't produce a funding
+    /// transaction that won't relay. explicitly chosen feerates are used as is
+    pub fn set_min_funding_feerate(&self, min_funding_feerate: FeeRate) {
+        let mut current = lock(&self.min_funding_feerate);
+        *current = min_funding_feerate;
+    }
+
+    /// the backend's feerate estimate for a funding transaction, raised
+    /// to the minimum funding feerate
+    fn estimate_funding_feerate(
+        &self,
+        wallet: &Wallet<B, D>,
+        target_blocks: usize,
+    ) -> Result<FeeRate, Error> {
+        let estimate = wallet.client().estimate_fee(target_blocks)?;
+        let min_funding_feerate = *lock(&self.min_funding_feerate);
+        if estimate.as_sat_vb() < min_funding_feerate.as_sat_vb() {
+            debug!(
+                "raising funding feerate estimate of {} sat/vbyte to {} sat/vbyte",
+                estimate.as_sat_vb(),
+                min_funding_feerate.as_sat_vb()
+            );
+            return Ok(min_funding_feerate);
+        }
+        Ok(estimate)
+    }
+
+    /// returns the feerate used by the most recently built funding
+    /// transaction, after the minimum funding feerate was applied, e.g.
+    /// for audit logs or to compare with the feerate ldk expects. fee
+    /// previews like estimate_funding_fee don't count
+    pub fn last_funding_feerate(&self) -> Option<FeeRate> {
+        *lock(&self.last_funding_feerate)
+    }
+
+    /// returns the fee (in sats) a channel funding transaction would pay
+    /// without signing or broadcasting it, e.g. to show before opening.
+    /// no change address is handed out, the estimate assumes change goes
+    /// to a script of the same type as your external addresses
+    pub fn estimate_funding_fee(
+        &self,
+        output_script: &Script,
+        value: u64,
+        target_blocks: usize,
+    ) -> Result<u64, Error> {
+        let options = FundingOptions {
+            fee: FundingFee::TargetBlocks(target_blocks),
+            ..Default::default()
+        };
+
+        let wallet = lock(&self.inner);
+        let change_script = wallet.get_address(AddressIndex::Peek(0))?.script_pubkey();
+        let (_psbt, tx_details, _fee_rate) = self.build_unsigned_funding_psbt(
+            &wallet,
+            FundingOutputs::Recipients(&[(output_script.clone(), value)]),
+            &options,
+            Some(change_script),
+        )?;
+
+        Ok(tx_details.fee.unwrap_or(0))
+    }
+
+    /// returns the weight a channel funding transaction for value would
+    /// have once signed, using the inputs bdk's coin selection picks now
+    /// and the largest possible witness for each of them. divide by 4
+    /// (rounding up) for the vsize. like estimate_funding_fee nothing is
+    /// signed and no change address is handed out, change is assumed to
+    /// go to a script of the same type as your first external address
+    pub fn estimate_funding_weight(
+        &self,
+        value: u64,
+        target_blocks: usize,
+    ) -> Result<usize, Error> {
+        let options = FundingOptions {
+            fee: FundingFee::TargetBlocks(target_blocks),
+            ..Default::default()
+        };
+        // every channel funding output is p2wsh, so the script's hash
+        // doesn't change the weight
+        let output_script = Script::new_v0_wsh(&WScriptHash::default());
+
+        let wallet = lock(&self.inner);
+        let change_script = wallet.get_address(AddressIndex::Peek(0))?.script_pubkey();
+        let (psbt, _tx_details, _fee_rate) = self.build_unsigned_funding_psbt(
+            &wallet,
+            FundingOutputs::Recipients(&[(output_script, value)]),
+            &options,
+            Some(change_script),
+        )?;
+
+        // the segwit marker and flag
+        let mut weight = psbt.global.unsigned_tx.get_weight() + 2;
+        for input in &psbt.global.unsigned_tx.input {
+            let keychain = wallet
+                .get_utxo(input.previous_output)?
+                .map_or(KeychainKind::External, |utxo| utxo.keychain);
+            weight += wallet
+                .get_descriptor_for_keychain(keychain)
+                .max_satisfaction_weight()
+                .map_err(|e| Error::Bdk(bdk::Error::Miniscript(e)))?;
+        }
+        Ok(weight)
+    }
+
+    /// builds the channel funding transaction and returns the psbt along
+    /// with its details (fee, amounts sent/received) for inspection
+    /// the psbt has already been signed by your bdk wallet, which is
+    /// all that is needed for the default single-sig case
+    pub fn construct_funding_psbt(
+        &self,
+        output_script: &Script,
+        value: u64,
+        fee_rate: FeeRate,
+    ) -> Result<(PartiallySignedTransaction, TransactionDetails), Error> {
+        let options = FundingOptions {
+            fee: FundingFee::FeeRate(fee_rate),
+            ..Default::default()
+        };
+        self.build_funding_psbt(
+            FundingOutputs::Recipients(&[(output_script.clone(), value)]),
+            &options,
+        )
+    }
+
+    /// same as construct_funding_transaction_with_feerate but only spends
+    /// the given utxos from your wallet, e.g. to keep coins from different
+    /// sources separate or to consolidate dust into a channel. returns
+    /// Error::InsufficientFunds if they can't cover the value plus fees
+    pub fn construct_funding_transaction_from_utxos(
+        &self,
+        output_script: &Script,
+        value: u64,
+        fee_rate: FeeRate,
+        utxos: &[BitcoinOutPoint],
+    ) -> Result<Transaction, Error> {
+        let options = FundingOptions {
+            fee: FundingFee::FeeRate(fee_rate),
+            utxos: utxos.to_vec(),
+            ..Default::default()
+        };
+        self.construct_funding_transaction_with_options(output_script, value, &options)
+    }
+
+    /// builds and signs a channel funding transaction using the given options
+    pub fn construct_funding_transaction_with_options(
+        &self,
+        output_script: &Script,
+        value: u64,
+        options: &FundingOptions,
+    ) -> Result<Transaction, Error> {
+        let (psbt, _tx_details) = self.build_funding_psbt(
+            FundingOutputs::Recipients(&[(output_script.clone(), value)]),
+            options,
+        )?;
+        extract_finalized_tx(psbt)
+    }
+
+    /// builds the channel funding transaction without signing it, for
+    /// hardware wallets and other external signers. once signed, turn
+    /// the psbt into a transaction with finalize_signed_psbt
+    pub fn construct_unsigned_funding_psbt(
+        &self,
+        output_script: &Script,
+        value: u64,
+        target_blocks: usize,
+    ) -> Result<PartiallySignedTransaction, Error> {
+        let options = FundingOptions {
+            fee: FundingFee::TargetBlocks(target_blocks),
+            ..Default::default()
+        };
+
+        let wallet = lock(&self.inner);
+        let (psbt, _tx_details, fee_rate) = self.build_unsigned_funding_psbt(
+            &wallet,
+            FundingOutputs::Recipients(&[(output_script.clone(), value)]),
+            &options,
+            None,
+        )?;
+
+        *lock(&self.last_funding_feerate) = Some(fee_rate);
+        Ok(psbt)
+    }
+
+    /// builds a channel funding transaction that also spends utxos from
+    /// outside your wallet and signs your wallet's inputs. the psbt is
+    /// returned rather than a transaction since the owners of the foreign
+    /// utxos still need to sign it, after which finalize_signed_psbt
+    /// turns it into a transaction ready to be broadcast
+    pub fn construct_funding_psbt_with_foreign_utxos(
+        &self,
+        output_script: &Script,
+        value: u64,
+        target_blocks: usize,
+        foreign_utxos: Vec<ForeignUtxo>,
+    ) -> Result<PartiallySignedTransaction, Error> {
+        let options = FundingOptions {
+            fee: FundingFee::TargetBlocks(target_blocks),
+            foreign_utxos,
+            ..Default::default()
+        };
+        let (psbt, _tx_details) = self.build_funding_psbt(
+            FundingOutputs::Recipients(&[(output_script.clone(), value)]),
+            &options,
+        )?;
+        Ok(psbt)
+    }
+
+    /// finalizes a psbt signed by an external signer and extracts the
+    /// transaction, ready to be broadcast
+    pub fn finalize_signed_psbt(
+        &self,
+        mut psbt: PartiallySignedTransaction,
+    ) -> Result<Transaction, Error> {
+        let wallet = lock(&self.inner);
+        let finalized = wallet.finalize_psbt(&mut psbt, SignOptions::default())?;
+        if !finalized {
+            return Err(Error::IncompleteSignature);
+        }
+        Ok(psbt.extract_tx())
+    }
+
+    /// funds several channels in one transaction, which is cheaper than
+    /// a funding transaction per channel. returns the transaction along
+    /// with the funding outpoint of each of the outputs, in the same order,
+    /// so every channel's funding outpoint can be handed to ldk
+    pub fn construct_batch_funding_transaction(
+        &self,
+        outputs: &[(Script, u64)],
+        target_blocks: usize,
+    ) -> Result<(Transaction, Vec<OutPoint>), Error> {
+        let options = FundingOptions {
+            fee: FundingFee::TargetBlocks(target_blocks),
+            ..Default::default()
+        };
+        let (psbt, _tx_details) =
+            self.build_funding_psbt(FundingOutputs::Recipients(outputs), &options)?;
+        let tx = extract_finalized_tx(psbt)?;
+        let outpoints = find_funding_outpoints(&tx, outputs)?;
+
+        Ok((tx, outpoints))
+    }
+
+    /// funds a channel with the entire balance of your wallet, letting bdk
+    /// work out the largest value that can be sent after fees. returns
+    /// the funding value alongside the transaction so it can be used
+    /// when opening the channel with ldk
+    pub fn construct_funding_transaction_drain(
+        &self,
+        output_script: &Script,
+        target_blocks: usize,
+    ) -> Result<(Transaction, u64), Error> {
+        let options = FundingOptions {
+            fee: FundingFee::TargetBlocks(target_blocks),
+            ..Default::default()
+        };
+        self.construct_funding_transaction_drain_with_options(output_script, &options)
+    }
+
+    /// same as construct_funding_transaction_drain using the given options.
+    /// every utxo they allow is spent (only options.utxos when given) and
+    /// the change policy is unused since there is no change
+    pub fn construct_funding_transaction_drain_with_options(
+        &self,
+        output_script: &Script,
+        options: &FundingOptions,
+    ) -> Result<(Transaction, u64), Error> {
+        let (psbt, _tx_details) =
+            self.build_funding_psbt(FundingOutputs::Drain(output_script), options)?;
+
+        let tx = extract_finalized_tx(psbt)?;
+        let value = tx
+            .output
+            .iter()
+            .filter(|output| &output.script_pubkey == output_script)
+            .map(|output| output.value)
+            .sum();
+
+        Ok((tx, value))
+    }
+
+    /// sends value to an external address (e.g. cold storage) using the
+    /// same builder as channel funding. returns Error::NetworkMismatch if
+    /// the address is for a different network than your wallet
+    pub fn send_to_address(
+        &self,
+        address: &Address,
+        value: u64,
+        fee_rate: FeeRate,
+    ) -> Result<Transaction, Error> {
+        check_address_network(address, lock(&self.inner).network())?;
+
+        let options = FundingOptions {
+            fee: FundingFee::FeeRate(fee_rate),
+            ..Default::default()
+        };
+        self.construct_funding_transaction_with_options(&address.script_pubkey(), value, &options)
+    }
+
+    fn build_funding_psbt(
+        &self,
+        outputs: FundingOutputs,
+        options: &FundingOptions,
+    ) -> Result<(PartiallySignedTransaction, TransactionDetails), Error> {
+        let wallet = lock(&self.inner);
+        let (mut psbt, tx_details, fee_rate) =
+            self.build_unsigned_funding_psbt(&wallet, outputs, options, None)?;
+
+        let _finalized = wallet.sign(&mut psbt, SignOptions::default())?;
+
+        *lock(&self.last_funding_feerate) = Some(fee_rate);
+        Ok((psbt, tx_details))
+    }
+
+    /// the wallet's utxos with fewer than min_confirmations as of the tip
+    /// last reported to ldk. before the first lightning sync the highest
+    /// confirmation the wallet knows of stands in for the tip, which can
+    /// only undercount confirmations
+    fn shallow_utxos(
+        &self,
+        wallet: &Wallet<B, D>,
+        min_confirmations: u32,
+    ) -> Result<Vec<BitcoinOutPoint>, Error> {
+        let confirmation_heights = confirmation_heights(wallet)?;
+        let tip_height = lock(&self.last_tip_height).unwrap_or_else(|| {
+            confirmation_heights
+                .values()
+                .flatten()
+                .copied()
+                .max()
+                .unwrap_or(0)
+        });
+
+        Ok(wallet
+            .list_unspent()?
+            .into_iter()
+            .filter(|utxo| {
+                let confirmations = match confirmation_heights.get(&utxo.outpoint.txid) {
+                    Some(Some(height)) => (tip_height + 1).saturating_sub(*height),
+                    _ => 0,
+                };
+                confirmations < min_confirmations
+            })
+            .map(|utxo| utxo.outpoint)
+            .collect())
+    }
+
+    /// change is sent to change_script when given, otherwise to a
+    /// fresh address from the wallet. also returns the feerate used
+    fn build_unsigned_funding_psbt(
+        &self,
+        wallet: &Wallet<B, D>,
+        outputs: FundingOutputs,
+        options: &FundingOptions,
+        change_script: Option<Script>,
+    ) -> Result<(PartiallySignedTransaction, TransactionDetails, FeeRate), Error> {
+        let recipients: &[(Script, u64)] = match outputs {
+            FundingOutputs::Recipients(recipients) => recipients,
+            FundingOutputs::Drain(_output_script) => &[],
+        };
+        for (output_script, value) in recipients {
+            let dust_limit = self.dust_limit(
+                output_script,
+                FeeRate::from_sat_per_vb(DUST_RELAY_FEE_SAT_PER_VB as f32),
+            );
+            if *value < dust_limit {
+                return Err(Error::DustValue {
+                    value: *value,
+                    dust_limit,
+                });
+            }
+        }
+        let value = recipients.iter().map(|(_script, value)| value).sum::<u64>();
+
+        if let Some(data) = &options.data {
+            if data.len() > MAX_OP_RETURN_DATA_LEN {
+                return Err(Error::DataTooLarge {
+                    len: data.len(),
+                    max: MAX_OP_RETURN_DATA_LEN,
+                });
+            }
+        }
+
+        let change_script = match (outputs, &options.change_policy) {
+            (FundingOutputs::Drain(output_script), _) => Some(output_script.clone()),
+            (FundingOutputs::Recipients(_), ChangePolicy::Internal) => change_script,
+            (FundingOutputs::Recipients(_), ChangePolicy::DrainTo(address)) => {
+                check_address_network(address, wallet.network())?;
+                Some(address.script_pubkey())
+            }
+        };
+
+        let fee_rate = match options.fee {
+            FundingFee::TargetBlocks(target_blocks) => {
+                self.estimate_funding_feerate(wallet, target_blocks)?
+            }
+            FundingFee::FeeRate(fee_rate) => fee_rate,
+        };
+
+        let estimated_fee = (fee_rate.as_sat_vb() * MIN_FUNDING_TX_VBYTES as f32).ceil() as u64;
+        let foreign_value = options
+            .foreign_utxos
+            .iter()
+            .filter_map(|(outpoint, psbt_input, _weight)| {
+                psbt_input
+                    .witness_utxo
+                    .as_ref()
+                    .or_else(|| {
+                        psbt_input
+                            .non_witness_utxo
+                            .as_ref()
+                            .and_then(|tx| tx.output.get(outpoint.vout as usize))
+                    })
+                    .map(|output| output.value)
+            })
+            .sum::<u64>();
+
+        let mut unspendable = self.unspendable_utxos();
+        if options.min_confirmations > 0 {
+            let shallow = self.shallow_utxos(wallet, options.min_confirmations)?;
+            if let Some(outpoint) = options
+                .utxos
+                .iter()
+                .find(|outpoint| shallow.contains(outpoint))
+            {
+                return Err(Error::ShallowUtxo {
+                    outpoint: *outpoint,
+                    min_confirmations: options.min_confirmations,
+                });
+            }
+            unspendable.extend(shallow);
+        }
+
+        // only what coin selection may actually spend: the chosen utxos,
+        // or every utxo that isn't unspendable or change
+        let unspent = wallet.list_unspent()?;
+        let selectable_value = unspent
+            .iter()
+            .filter(|utxo| {
+                if options.utxos.is_empty() {
+                    utxo.keychain == KeychainKind::External && !unspendable.contains(&utxo.outpoint)
+                } else {
+                    options.utxos.contains(&utxo.outpoint)
+                }
+            })
+            .map(|utxo| utxo.txout.value)
+            .sum::<u64>();
+        let available = selectable_value + foreign_value;
+        if value + estimated_fee > available {
+            return Err(Error::InsufficientFunds {
+                needed: value + estimated_fee,
+                available,
+            });
+        }
+
+        let mut tx_builder = wallet.build_tx();
+
+        if !options.utxos.is_empty() {
+            tx_builder
+                .add_utxos(&options.utxos)?
+                .manually_selected_only();
+        } else if let FundingOutputs::Drain(_output_script) = outputs {
+            tx_builder.drain_wallet();
+        }
+
+        for (outpoint, psbt_input, satisfaction_weight) in &options.foreign_utxos {
+            tx_builder.add_foreign_utxo(*outpoint, psbt_input.clone(), *satisfaction_weight)?;
+        }
+
+        for (output_script, value) in recipients {
+            tx_builder.add_recipient(output_script.clone(), *value);
+        }
+
+        tx_builder
+            .unspendable(unspendable)
+            .fee_rate(fee_rate)
+            .do_not_spend_change();
+
+        if options.rbf {
+            tx_builder.enable_rbf();
+        }
+
+        if let Some(data) = &options.data {
+            tx_builder.add_data(data);
+        }
+
+        if let Some(change_script) = change_script {
+            tx_builder.drain_to(change_script);
+        }
+
+        let (psbt, tx_details) = tx_builder.finish()?;
+
+        if let FundingOutputs::Drain(output_script) = outputs {
+            let value = psbt
+                .global
+                .unsigned_tx
+                .output
+                .iter()
+                .filter(|output| &output.script_pubkey == output_script)
+                .map(|output| output.value)
+                .sum();
+            let dust_limit = self.dust_limit(
+                output_script,
+                FeeRate::from_sat_per_vb(DUST_RELAY_FEE_SAT_PER_VB as f32),
+            );
+            if value < dust_limit {
+                return Err(Error::DustValue { value, dust_limit });
+            }
+        }
+
+        Ok((psbt, tx_details, fee_rate))
+    }
+
+    /// replaces a stuck unconfirmed transaction from your wallet (e.g. a
+    /// channel funding or sweep) with one paying the new feerate and
+    /// returns the signed replacement, ready to be broadcast. returns
+    /// Error::AlreadyConfirmed or Error::NotReplaceable when the original
+    /// transaction can't be replaced. the backend is asked whether it
+    /// confirmed since the last sync, see is_confirmed.
+    pub fn bump_fee(&self, txid: &Txid, new_fee_rate: FeeRate) -> Result<Transaction, Error> {
+        if self.is_confirmed(txid)? {
+            return Err(Error::AlreadyConfirmed);
+        }
+
+        let wallet = lock(&self.inner);
+
+        let mut tx_builder = wallet.build_fee_bump(*txid)?;
+        tx_builder
+            .unspendable(self.unspendable_utxos())
+            .fee_rate(new_fee_rate)
+            .enable_rbf();
+
+        let (mut psbt, _tx_details) = tx_builder.finish()?;
+
+        let _finalized = wallet.sign(&mut psbt, SignOptions::default())?;
+
+        extract_finalized_tx(psbt)
+    }
+
+    /// creates a child transaction spending the given output of an unconfirmed
+    /// parent (plus more of your wallet's utxos if needed) back into your wallet
+    /// paying enough fee for the parent and child together to reach fee_rate.
+    /// the parent must be a transaction from your wallet and the output must
+    /// belong to your wallet, e.g. the change of a stuck channel funding.
+    pub fn create_cpfp_transaction(
+        &self,
+        parent_txid: &Txid,
+        parent_vout: u32,
+        fee_rate: FeeRate,
+    ) -> Result<Transaction, Error> {
+        let wallet = lock(&self.inner);
+
+        let parent = wallet
+            .list_transactions(true)?
+            .into_iter()
+            .find(|details| details.txid == *parent_txid)
+            .ok_or(Error::UnknownTransaction(*parent_txid))?;
+        let parent_fee = parent.fee.unwrap_or(0);
+        let parent_vsize = parent
+            .transaction
+            .map(|tx| (tx.get_weight() as u64 + 3) / 4)
+            .ok_or(Error::UnknownTransaction(*parent_txid))?;
+
+        let drain_script = wallet
+            .get_address(AddressIndex::LastUnused)?
+            .address
+            .script_pubkey();
+        let outpoint = BitcoinOutPoint::new(*parent_txid, parent_vout);
+
+        // build the child once at the target feerate to learn its size
+        let mut tx_builder = wallet.build_tx();
+        tx_builder
+            .add_utxo(outpoint)?
+            .unspendable(self.unspendable_utxos())
+            .drain_to(drain_script.clone())
+            .fee_rate(fee_rate)
+            .enable_rbf();
+        let (mut psbt, _tx_details) = tx_builder.finish()?;
+        let _finalized = wallet.sign(&mut psbt, SignOptions::default())?;
+        let child_vsize = (extract_finalized_tx(psbt)?.get_weight() as u64 + 3) / 4;
+
+        let package_fee =
+            (fee_rate.as_sat_vb() * (parent_vsize + child_vsize) as f32).ceil() as u64;
+        let child_fee = package_fee.saturating_sub(parent_fee).max(child_vsize);
+
+        let mut tx_builder = wallet.build_tx();
+        tx_builder
+            .add_utxo(outpoint)?
+            .unspendable(self.unspendable_utxos())
+            .drain_to(drain_script)
+            .fee_absolute(child_fee)
+            .enable_rbf();
+        let (mut psbt, _tx_details) = tx_builder.finish()?;
+        let _finalized = wallet.sign(&mut psbt, SignOptions::default())?;
+
+        extract_finalized_tx(psbt)
+    }
+
+    /// sweeps the outputs ldk hands you in Event::SpendableOutputs back
+    /// into your onchain wallet. only StaticOutputs can be swept this way,
+    /// the other descriptors need ldk's KeysManager to sign them.
+    pub fn create_sweep_transaction(
+        &self,
+        descriptors: &[SpendableOutputDescriptor],
+        fee_rate: FeeRate,
+    ) -> Result<Transaction, Error> {
+        self.create_sweep_transaction_with_min_value(descriptors, fee_rate, 0)
+    }
+
+    /// same as create_sweep_transaction but outputs worth less than
+    /// min_sweep_value are left out, so many small outputs from closed
+    /// channels can be swept together without paying for the dust.
+    /// returns Error::UneconomicalSweep if what remains doesn't cover the fee
+    pub fn create_sweep_transaction_with_min_value(
+        &self,
+        descriptors: &[SpendableOutputDescriptor],
+        fee_rate: FeeRate,
+        min_sweep_value: u64,
+    ) -> Result<Transaction, Error> {
+        self.create_sweep_transaction_to_keychain(
+            descriptors,
+            fee_rate,
+            min_sweep_value,
+            KeychainKind::External,
+        )
+    }
+
+    /// same as create_sweep_transaction_with_min_value but sweeps to the
+    /// last unused address of the given keychain
+    pub fn create_sweep_transaction_to_keychain(
+        &self,
+        descriptors: &[SpendableOutputDescriptor],
+        fee_rate: FeeRate,
+        min_sweep_value: u64,
+        keychain: KeychainKind,
+    ) -> Result<Transaction, Error> {
+        if descriptors
+            .iter()
+            .any(|descriptor| !matches!(descriptor, SpendableOutputDescriptor::StaticOutput { .. }))
+        {
+            return Err(Error::UnsupportedSpendableOutput);
+        }
+
+        let outputs = descriptors
+            .iter()
+            .filter_map(|descriptor| match descriptor {
+                SpendableOutputDescriptor::StaticOutput { outpoint, output }
+                    if output.value >= min_sweep_value =>
+                {
+                    Some((outpoint, output))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        let value = outputs.iter().map(|(_outpoint, output)| output.value).sum();
+        let vbytes = SWEEP_TX_BASE_VBYTES + SWEEP_INPUT_VBYTES * outputs.len() as u64;
+        let fee = (fee_rate.as_sat_vb() * vbytes as f32).ceil() as u64;
+        if value <= fee {
+            return Err(Error::UneconomicalSweep { value, fee });
+        }
+
+        let wallet = lock(&self.inner);
+        let address_info = keychain_address(&wallet, AddressIndex::LastUnused, keychain)?;
+
+        let mut tx_builder = wallet.build_tx();
+
+        for (outpoint, output) in outputs {
+            let psbt_input = psbt::Input {
+                witness_utxo: Some(output.clone()),
+                ..Default::default()
+            };
+            tx_builder.add_foreign_utxo(
+                outpoint.into_bitcoin_outpoint(),
+                psbt_input,
+                P2WPKH_SATISFACTION_WEIGHT,
+            )?;
+        }
+
+        tx_builder
+            .drain_to(address_info.address.script_pubkey())
+            .manually_selected_only()
+            .only_witness_utxo()
+            .fee_rate(fee_rate)
+            .enable_rbf();
+
+        let (mut psbt, _tx_details) = tx_builder.finish()?;
+
+        let sign_options = SignOptions {
+            trust_witness_utxo: true,
+            ..Default::default()
+        };
+        let _finalized = wallet.sign(&mut psbt, sign_options)?;
+
+        extract_finalized_tx(psbt)
+    }
+
+    fn estimate_sat_per_1000_weight(
+        &self,
+        confirmation_target: ConfirmationTarget,
+    ) -> Result<u32, Error> {
+        let target_blocks = lock(&self.fee_targets).target_blocks(confirmation_target);
+        self.backend_sat_per_1000_weight(target_blocks)
+    }
+
+    fn backend_sat_per_1000_weight(&self, target_blocks: usize) -> Result<u32, Error> {
+        let wallet = lock(&self.inner);
+        let estimate = wallet.client().estimate_fee(target_blocks)?;
+        Ok(fee_rate_to_sat_per_1000_weight(estimate))
+    }
+
+    fn apply_fee_multiplier(&self, sats_per_1000_weight: u32) -> u32 {
+        let fee_multiplier = *lock(&self.fee_multiplier);
+        (sats_per_1000_weight as f64 * fee_multiplier).round() as u32
+    }
+
+    /// estimates the feerate in sats per 1000 weight units (sat/kw) to
+    /// confirm within any number of blocks, not just the three targets
+    /// ldk asks for, e.g. 144 blocks for a cheap sweep. the fee multiplier
+    /// and ldk's FEERATE_FLOOR_SATS_PER_KW are applied like in
+    /// get_est_sat_per_1000_weight, but nothing is cached and backend
+    /// errors are returned instead of falling back
+    pub fn estimate_feerate_for_blocks(&self, target_blocks: usize) -> Result<u32, Error> {
+        let sats_per_1000_weight = self.backend_sat_per_1000_weight(target_blocks)?;
+        Ok(self
+            .apply_fee_multiplier(sats_per_1000_weight)
+            .max(FEERATE_FLOOR_SATS_PER_KW))
+    }
+
+    /// syncs only your onchain wallet to the current tip
+    ///
+    /// bdk's Wallet::sync interleaves network requests with database
+    /// writes and the wallet isn't Sync, so the wallet lock is held for the
+    /// whole onchain sync and anything needing the wallet (balances,
+    /// addresses, funding, backend queries) waits for it to finish.
+    /// ldk's FeeEstimator answers from the fee cache, fee overrides or
+    /// fee source without the wallet lock, so keep the cache warm with
+    /// refresh_fee_cache to stop fee estimation stalling behind a sync
+    pub fn sync_onchain_wallet(&self) -> Result<(), Error> {
+        let started_at = Instant::now();
+        debug!("syncing onchain wallet");
+
+        let wallet = lock(&self.inner);
+        wallet
+            .sync(noop_progress(), None)
+            .map_err(Error::OnchainSync)?;
+        let unspent = wallet
+            .list_unspent()
+            .map_err(Error::OnchainSync)?
+            .into_iter()
+            .map(|utxo| utxo.outpoint)
+            .collect::<HashSet<BitcoinOutPoint>>();
+        drop(wallet);
+
+        lock(&self.locked_utxos).retain(|outpoint| unspent.contains(outpoint));
+        *lock(&self.last_sync_time) = Some(Instant::now());
+
+        debug!("synced onchain wallet in {:?}", started_at.elapsed());
+        Ok(())
+    }
+
+    /// when the onchain wallet last finished syncing, or None if it
+    /// hasn't synced since this wallet was created
+    pub fn last_sync(&self) -> Option<Instant> {
+        *lock(&self.last_sync_time)
+    }
+
+    /// whether the onchain wallet hasn't synced within max_age, e.g. to
+    /// warn before trusting balances or opening channels. a wallet that
+    /// has never synced is always stale
+    pub fn is_stale(&self, max_age: Duration) -> bool {
+        self.last_sync()
+            .map_or(true, |last_sync| last_sync.elapsed() > max_age)
+    }
+
+    /// summarizes the wallet's state in one call, e.g. for a monitoring
+    /// scrape. the tip last reported to ldk and the time of the last
+    /// onchain sync are reused, so the backend is only queried for the
+    /// tip before the first lightning sync. the balance comes from the
+    /// wallet's database and is as fresh as the last onchain sync
+    pub fn health(&self) -> Result<WalletHealth, Error> {
+        let cached_tip_height = *lock(&self.last_tip_height);
+        let tip_height = match cached_tip_height {
+            Some(tip_height) => tip_height,
+            None => self.with_retry(|client| client.get_height())?,
+        };
+
+        let wallet = lock(&self.inner);
+        let confirmation_heights = confirmation_heights(&wallet)?;
+        let confirmed_balance = wallet
+            .list_unspent()?
+            .into_iter()
+            .filter(|utxo| matches!(confirmation_heights.get(&utxo.outpoint.txid), Some(Some(_))))
+            .map(|utxo| utxo.txout.value)
+            .sum();
+        drop(wallet);
+
+        let filter = lock(&self.filter);
+        let watched_count = filter.watched_transactions.len() + filter.watched_outputs.len();
+        drop(filter);
+
+        Ok(WalletHealth {
+            tip_height,
+            confirmed_balance,
+            last_sync_age: self.last_sync().map(|last_sync| last_sync.elapsed()),
+            watched_count,
+        })
+    }
+
+    fn sync_lightning(
+        &self,
+        channel_manager: Arc<dyn Confirm>,
+        chain_monitor: Arc<dyn Confirm>,
+        target_height: Option<u32>,
+        errors: &mut SyncErrors,
+    ) -> Result<SyncStats, Error> {
+        let skipped = errors.errors.len();
+        let result = self.notify_lightning(channel_manager, chain_monitor, target_height, errors);
+
+        let lightning_errors = errors.errors.split_off(skipped);
+        errors
+            .errors
+            .extend(lightning_errors.into_iter().map(Error::in_lightning_sync));
+        result.map_err(Error::in_lightning_sync)
+    }
+
+    fn notify_lightning(
+        &self,
+        channel_manager: Arc<dyn Confirm>,
+        chain_monitor: Arc<dyn Confirm>,
+        target_height: Option<u32>,
+        errors: &mut SyncErrors,
+    ) -> Result<SyncStats, Error> {
+        let started_at = Instant::now();
+
+        if let Some(height) = target_height {
+            let tip = self.with_retry(|client| client.get_height())?;
+            if height > tip {
+                return Err(Error::HeightAboveTip { height, tip });
+            }
+        }
+
+        let mut relevant_txids = channel_manager.get_relevant_txids();
+        relevant_txids.append(&mut chain_monitor.get_relevant_txids());
+        relevant_txids.extend(lock(&self.filter).confirmed_txids.iter());
+        relevant_txids.sort_unstable();
+        relevant_txids.dedup();
+
+        let checked = relevant_txids.len();
+        debug!("syncing lightning, checking {} relevant txids", checked);
+
+        // e.g. a freshly started node without channels, where the only
+        // thing to tell ldk about is the new tip
+        let nothing_to_check = relevant_txids.is_empty() && lock(&self.filter).is_empty();
+        if nothing_to_check {
+            debug!("nothing watched, only updating the best block");
+        }
+
+        let unconfirmed_txids = if nothing_to_check {
+            vec![]
+        } else {
+            self.get_unconfirmed(relevant_txids, errors)?
+        };
+        let unconfirmed = unconfirmed_txids.len();
+        for unconfirmed_txid in unconfirmed_txids {
+            info!("transaction {} is no longer confirmed", unconfirmed_txid);
+            channel_manager.transaction_unconfirmed(&unconfirmed_txid);
+            chain_monitor.transaction_unconfirmed(&unconfirmed_txid);
+            lock(&self.filter).forget_confirmed(&unconfirmed_txid);
+            self.forget_positions(&unconfirmed_txid);
+        }
+
+        let mut confirmed_txs = if nothing_to_check {
+            vec![]
+        } else {
+            self.get_confirmed_txs_by_block(errors)?
+        };
+        if let Some(target_height) = target_height {
+            confirmed_txs.retain(|(height, _header, _tx_list)| *height <= target_height);
+        }
+
+        let mut confirmed = 0;
+        let mut newly_confirmed = vec![];
+        for (height, header, tx_list) in confirmed_txs {
+            let tx_list_ref = tx_list
+                .iter()
+                .map(|(height, tx)| (height.to_owned(), tx))
+                .collect::<Vec<(usize, &Transaction)>>();
+
+            for (_pos, tx) in tx_list.iter() {
+                info!("transaction {} confirmed at height {}", tx.txid(), height);
+            }
+
+            channel_manager.transactions_confirmed(&header, tx_list_ref.as_slice(), height);
+            chain_monitor.transactions_confirmed(&header, tx_list_ref.as_slice(), height);
+
+            let mut filter = lock(&self.filter);
+            for (_pos, tx) in tx_list.iter() {
+                if filter.record_confirmed(tx) {
+                    newly_confirmed.push((tx.txid(), height));
+                }
+            }
+            confirmed += tx_list.len();
+        }
+
+        let on_confirmed = lock(&self.on_confirmed).clone();
+        if let Some(on_confirmed) = on_confirmed {
+            for (txid, height) in newly_confirmed {
+                on_confirmed(txid, height);
+            }
+        }
+
+        let (tip_height, tip_header) = match target_height {
+            Some(height) => {
+                let header = self.with_retry(|client| client.get_header(height))?;
+                (height, header)
+            }
+            None => self.get_tip()?,
+        };
+
+        *lock(&self.last_tip_height) = Some(tip_height);
+        channel_manager.best_block_updated(&tip_header, tip_height);
+        chain_monitor.best_block_updated(&tip_header, tip_height);
+
+        let elapsed = started_at.elapsed();
+        debug!(
+            "synced lightning to tip {} in {:?}, {} confirmed and {} unconfirmed",
+            tip_height, elapsed, confirmed, unconfirmed
+        );
+
+        Ok(SyncStats {
+            checked,
+            confirmed,
+            unconfirmed,
+            tip_height,
+            elapsed,
+        })
+    }
+
+    fn get_unconfirmed(
+        &self,
+        txids: Vec<Txid>,
+        errors: &mut SyncErrors,
+    ) -> Result<Vec<Txid>, Error> {
+        if let Some(batch_tx_status) = lock(&self.batch_tx_status).as_ref() {
+            let statuses = batch_tx_status(&txids)
+                .map_err(Error::Bdk)
+                .and_then(|statuses| {
+                    if statuses.len() != txids.len() {
+                        return Err(Error::Bdk(bdk::Error::Generic(format!(
+                            "batched lookup returned {} statuses for {} txids",
+                            statuses.len(),
+                            txids.len()
+                        ))));
+                    }
+                    Ok(statuses)
+                });
+            let statuses = match errors.check(statuses)? {
+                Some(statuses) => statuses,
+                None => return Ok(vec![]),
+            };
+
+            return Ok(txids
+                .into_iter()
+                .zip(statuses)
+                .filter(|(_txid, status)| !status.as_ref().map_or(false, |s| s.confirmed))
+                .map(|(txid, _status)| txid)
+                .collect());
+        }
+
+        let mut unconfirmed = vec![];
+        for txid in txids {
+            let status = self.augment_txid_with_confirmation_status(txid);
+            if let Some((txid, false)) = errors.check(status)? {
+                unconfirmed.push(txid);
+            }
+        }
+        Ok(unconfirmed)
+    }
+
+    /// calls f with each block holding confirmed watched transactions (the
+    /// ones ldk registered through Filter) in ascending height, and each
+    /// block's transactions in ascending position, the order sync hands
+    /// them to ldk. every watched script's history is fetched up front since
+    /// ordering needs all of it, then each block's header is fetched right
+    /// before f is called with that block. no locks are held while f runs
+    /// so it may call back into this LightningWallet
+    pub fn for_each_confirmed_block<F>(&self, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(u32, &BlockHeader, &[TransactionWithPosition]),
+    {
+        self.visit_confirmed_txs_by_block(&mut SyncErrors::new(false), |height, header, tx_list| {
+            f(height, &header, &tx_list)
+        })
+    }
+
+    /// confirmed watched transactions grouped by block, in ascending
+    /// height so transactions_confirmed is never called out of order
+    fn get_confirmed_txs_by_block(
+        &self,
+        errors: &mut SyncErrors,
+    ) -> Result<Vec<(u32, BlockHeader, Vec<TransactionWithPosition>)>, Error> {
+        let mut confirmed_by_block = vec![];
+        self.visit_confirmed_txs_by_block(errors, |height, header, tx_list| {
+            confirmed_by_block.push((height, header, tx_list))
+        })?;
+        Ok(confirmed_by_block)
+    }
+
+    fn visit_confirmed_txs_by_block<F>(
+        &self,
+        errors: &mut SyncErrors,
+        mut visit: F,
+    ) -> Result<(), Error>
+    where
+        F: FnMut(u32, BlockHeader, Vec<TransactionWithPosition>),
+    {
+        // ldk expects blocks in ascending height and each block's
+        // transactions in the order they appear within it
+        let mut txs_by_block: BTreeMap<u32, Vec<TransactionWithPosition>> = BTreeMap::new();
+
+        // a snapshot, so ldk can register more while the backend is queried
+        let (watched_transactions, watched_outputs) = {
+            let filter = lock(&self.filter);
+            (
+                filter.watched_transactions.clone(),
+                filter.watched_outputs.clone(),
+            )
+        };
+
+        // many watched items share a script (e.g. a funding output and the
+        // funding tx) so each distinct script history is only fetched once
+        let mut scripts = watched_transactions
+            .iter()
+            .map(|(_txid, script)| script)
+            .chain(watched_outputs.iter().map(|output| &output.script_pubkey))
+            .collect::<Vec<&Script>>();
+        scripts.sort_unstable();
+        scripts.dedup();
+
+        let confirmed_by_script = self.get_confirmed_txs_by_script(scripts, errors)?;
+
+        let mut confirmed_txs = watched_transactions
+            .iter()
+            .filter_map(|(txid, script)| {
+                confirmed_by_script
+                    .get(script)?
+                    .iter()
+                    .find(|(_height, tx)| tx.txid().eq(txid))
+                    .cloned()
+            })
+            .collect::<Vec<TransactionWithHeight>>();
+
+        let mut confirmed_spent = watched_outputs
+            .iter()
+            .flat_map(|output| {
+                confirmed_by_script
+                    .get(&output.script_pubkey)
+                    .cloned()
+                    .unwrap_or_default()
+            })
+            .collect::<Vec<TransactionWithHeight>>();
+
+        confirmed_txs.append(&mut confirmed_spent);
+
+        // a tx can be both watched by txid and spend a watched output
+        // but must only be reported to ldk once
+        let mut seen_txids = HashSet::new();
+        confirmed_txs.retain(|(_height, tx)| seen_txids.insert(tx.txid()));
+
+        for (height, tx) in confirmed_txs {
+            let position = self.augment_with_position(height, tx);
+            if let Some(Some((height, tx, pos))) = errors.check(position)? {
+                txs_by_block.entry(height).or_default().push((pos, tx))
+            }
+        }
+
+        for tx_list in txs_by_block.values_mut() {
+            tx_list.sort_by_key(|(pos, _tx)| *pos);
+        }
+
+        for (height, tx_list) in txs_by_block {
+            let block = self.augment_with_header(height, tx_list);
+            if let Some((height, header, tx_list)) = errors.check(block)? {
+                visit(height, header, tx_list);
+            }
+        }
+        Ok(())
+    }
+
+    /// returns the height of the block the transaction confirmed in
+    /// or None if it is unconfirmed or unknown to the backend
+    pub fn get_confirmation_height(&self, txid: &Txid) -> Result<Option<u32>, Error> {
+        self.augment_txid_with_confirmation_height(*txid)
+            .map(|(_txid, height)| height)
+    }
+
+    /// returns true if the transaction confirmed, according to the backend
+    /// rather than the last sync, or if the wallet knows of a confirmed
+    /// transaction spending one of its inputs, e.g. a different replacement
+    pub fn is_confirmed(&self, txid: &Txid) -> Result<bool, Error> {
+        let status = self.get_tx_status(*txid)?;
+        if status.map_or(false, |status| status.confirmed) {
+            return Ok(true);
+        }
+
+        let wallet = lock(&self.inner);
+        let transactions = wallet.list_transactions(true)?;
+        let spent = transactions
+            .iter()
+            .find(|details| details.txid == *txid)
+            .and_then(|details| details.transaction.as_ref())
+            .map(|tx| {
+                tx.input
+                    .iter()
+                    .map(|input| input.previous_output)
+                    .collect::<HashSet<_>>()
+            })
+            .unwrap_or_default();
+
+        Ok(transactions
+            .iter()
+            .filter(|details| details.txid != *txid && details.confirmation_time.is_some())
+            .filter_map(|details| details.transaction.as_ref())
+            .any(|tx| {
+                tx.input
+                    .iter()
+                    .any(|input| spent.contains(&input.previous_output))
+            }))
+    }
+
+    /// returns each of the txids (e.g. ldk's get_relevant_txids) with the
+    /// height it confirmed at, or None if it is unconfirmed or unknown to
+    /// the backend. useful for showing which of ldk's transactions have
+    /// confirmed when debugging, it doesn't notify ldk of anything
+    pub fn reconcile_relevant(&self, txids: &[Txid]) -> Result<Vec<(Txid, Option<u32>)>, Error> {
+        txids
+            .iter()
+            .map(|txid| self.augment_txid_with_confirmation_height(*txid))
+            .collect()
+    }
+
+    /// returns the txid of the confirmed transaction spending the outpoint
+    /// or None if it is unspent. the script is the one the outpoint pays
+    /// to, whose history is searched for the spend
+    pub fn is_output_spent(
+        &self,
+        script: &Script,
+        outpoint: BitcoinOutPoint,
+    ) -> Result<Option<Txid>, Error> {
+        let spend = self.find_confirmed_spend(script, outpoint)?;
+        Ok(spend.map(|(_height, tx)| tx.txid()))
+    }
+
+    /// returns the height, block hash and block time of each of the given
+    /// transactions that has confirmed, e.g. to display alongside
+    /// channel activity. unconfirmed and unknown transactions are omitted
+    pub fn get_confirmed_relevant_txs(
+        &self,
+        txids: &[Txid],
+    ) -> Result<Vec<(Txid, u32, BlockHash, u32)>, Error> {
+        let mut confirmed = vec![];
+        for txid in txids {
+            let height = self
+                .get_tx_status(*txid)?
+                .filter(|status| status.confirmed)
+                .and_then(|status| status.block_height);
+
+            if let Some(height) = height {
+                let (height, header, _tx_list) = self.augment_with_header(height, vec![])?;
+                confirmed.push((*txid, height, header.block_hash(), header.time));
+            }
+        }
+        Ok(confirmed)
+    }
+
+    /// returns how many confirmations the transaction has as of the
+    /// current tip (1 when it is in the tip block) or None if unconfirmed
+    pub fn confirmation_depth(&self, txid: &Txid) -> Result<Option<u32>, Error> {
+        let confirmation_height = match self.get_confirmation_height(txid)? {
+            Some(height) => height,
+            None => return Ok(None),
+        };
+
+        let tip_height = self.with_retry(|client| client.get_height())?;
+        Ok(Some(tip_height.saturating_sub(confirmation_height) + 1))
+    }
+
+    /// returns the height and header of the current best block
+    /// useful when initializing ldk's ChannelManager or showing sync status
+    pub fn get_tip(&self) -> Result<(u32, BlockHeader), Error> {
+        let mut tip_height = self.with_retry(|client| client.get_height())?;
+
+        // a block can arrive between fetching the height and the header.
+        // only return once the tip is unchanged across both calls so a
+        // reorg in between can't pair a height with a stale header
+        for _ in 0..MAX_TIP_ATTEMPTS {
+            let tip_header = self.with_retry(|client| client.get_header(tip_height))?;
+            let current_height = self.with_retry(|client| client.get_height())?;
+            if current_height == tip_height {
+                return Ok((tip_height, tip_header));
+            }
+            debug!(
+                "tip moved from {} to {} while fetching it",
+                tip_height, current_height
+            );
+            tip_height = current_height;
+        }
+
+        let tip_header = self.with_retry(|client| client.get_header(tip_height))?;
+        Ok((tip_height, tip_header))
+    }
+
+    /// returns the current best block as ldk's BestBlock, e.g. for the
+    /// ChainParameters used when creating a new ChannelManager
+    pub fn best_block(&self) -> Result<BestBlock, Error> {
+        let (tip_height, tip_header) = self.get_tip()?;
+        Ok(BestBlock::new(tip_header.block_hash(), tip_height))
+    }
+
+    /// runs the backend call, retrying transient errors with
+    /// exponential backoff according to the retry config. the wallet is
+    /// locked for each attempt but not while waiting to retry, so it
+    /// must not already be locked by the caller. sync therefore locks the
+    /// wallet once per backend call rather than once for the whole
+    /// confirmed-tx computation: holding it across a backoff would stall
+    /// every other wallet call for the length of the retries
+    fn with_retry<T>(
+        &self,
+        mut call: impl FnMut(&B) -> Result<T, bdk::Error>,
+    ) -> Result<T, bdk::Error> {
+        let retry_config = *lock(&self.retry_config);
+        let mut delay = retry_config.base_delay;
+        let mut attempt = 1;
+        loop {
+            let result = {
+                let wallet = lock(&self.inner);
+                call(wallet.client())
+            };
+            match result {
+                Err(e) if attempt < retry_config.max_attempts && is_transient(&e) => {
+                    debug!("retrying backend call in {:?} after error: {}", delay, e);
+                    std::thread::sleep(delay);
+                    delay *= 2;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn augment_txid_with_confirmation_status(&self, txid: Txid) -> Result<(Txid, bool), Error> {
+        self.get_tx_status(txid).map(|status| match status {
+            Some(status) => (txid, status.confirmed),
+            None => (txid, false),
+        })
+    }
+
+    fn augment_txid_with_confirmation_height(
+        &self,
+        txid: Txid,
+    ) -> Result<(Txid, Option<u32>), Error> {
+        self.get_tx_status(txid).map(|status| {
+            let height = status
+                .filter(|status| status.confirmed)
+                .and_then(|status| status.block_height);
+            (txid, height)
+        })
+    }
+
+    fn get_tx_status(&self, txid: Txid) -> Result<Option<TxStatus>, Error> {
+        self.with_retry(|client| client.get_tx_status(&txid))
+            .map_err(Error::Bdk)
+    }
+
+    fn get_confirmed_spend(
+        &self,
+        output: &WatchedOutput,
+    ) -> Result<Option<TransactionWithHeightAndPosition>, Error> {
+        let outpoint = output.outpoint.into_bitcoin_outpoint();
+        match self.find_confirmed_spend(&output.script_pubkey, outpoint)? {
+            Some((height, tx)) => self.augment_with_position(height, tx),
+            None => Ok(None),
+        }
+    }
+
+    fn find_confirmed_spend(
+        &self,
+        script: &Script,
+        outpoint: BitcoinOutPoint,
+    ) -> Result<Option<TransactionWithHeight>, Error> {
+        Ok(self
+            .get_confirmed_txs(script)?
+            .into_iter()
+            .find(|(_height, tx)| {
+                tx.input
+                    .iter()
+                    .any(|input| input.previous_output == outpoint)
+            }))
+    }
+
+    fn get_confirmed_txs_from_script_history(
+        &self,
+        history: Vec<(TxStatus, Transaction)>,
+    ) -> Vec<TransactionWithHeight> {
+        history
+            .into_iter()
+            .filter(|(status, _tx)| status.confirmed)
+            .filter_map(|(status, tx)| match status.block_height {
+                Some(height) => Some((height, tx)),
+                None => {
+                    // some backends transiently report a confirmed tx
+                    // without its height, it will be picked up next sync
+                    warn!("skipping tx {} confirmed without a block height", tx.txid());
+                    None
+                }
+            })
+            .collect::<Vec<TransactionWithHeight>>()
+    }
+
+    /// the confirmed history of each script, through the batched lookup
+    /// when one is set. scripts whose lookup failed are left out when
+    /// errors are collected
+    fn get_confirmed_txs_by_script<'a>(
+        &self,
+        scripts: Vec<&'a Script>,
+        errors: &mut SyncErrors,
+    ) -> Result<HashMap<&'a Script, Vec<TransactionWithHeight>>, Error> {
+        let mut confirmed_by_script = HashMap::new();
+
+        if let Some(batch_script_history) = lock(&self.batch_script_history).as_ref() {
+            let script_batch_size = *lock(&self.script_batch_size);
+            for batch in scripts.chunks(script_batch_size) {
+                trace!("querying history of {} watched scripts", batch.len());
+                let batch_scripts = batch
+                    .iter()
+                    .map(|script| (*script).clone())
+                    .collect::<Vec<Script>>();
+                let histories = batch_script_history(&batch_scripts)
+                    .map_err(Error::Bdk)
+                    .and_then(|histories| {
+                        if histories.len() != batch.len() {
+                            return Err(Error::Bdk(bdk::Error::Generic(format!(
+                                "batched lookup returned {} histories for {} scripts",
+                                histories.len(),
+                                batch.len()
+                            ))));
+                        }
+                        Ok(histories)
+                    });
+                if let Some(histories) = errors.check(histories)? {
+                    for (script, history) in batch.iter().zip(histories) {
+                        let confirmed = self.get_confirmed_txs_from_script_history(history);
+                        confirmed_by_script.insert(*script, confirmed);
+                    }
+                }
+            }
+            return Ok(confirmed_by_script);
+        }
+
+        for script in scripts {
+            trace!("querying history of watched script {}", script);
+            if let Some(confirmed) = errors.check(self.get_confirmed_txs(script))? {
+                confirmed_by_script.insert(script, confirmed);
+            }
+        }
+        Ok(confirmed_by_script)
+    }
+
+    fn get_confirmed_txs(&self, script: &Script) -> Result<Vec<TransactionWithHeight>, Error> {
+        self.with_retry(|client| client.get_script_tx_history(script))
+            .map(|history| self.get_confirmed_txs_from_script_history(history))
+            .map_err(Error::Bdk)
+    }
+
+    fn augment_with_position(
+        &self,
+        height: u32,
+        tx: Transaction,
+    ) -> Result<Option<TransactionWithHeightAndPosition>, Error> {
+        let txid = tx.txid();
+        if let Some(pos) = self.get_position_in_block(txid, height)? {
+            return Ok(Some((height, tx, pos)));
+        }
+
+        // the tx is not in the block we were told about, it may have been
+        // reorged into another block since its history was fetched
+        let current_height = self
+            .get_tx_status(txid)?
+            .filter(|status| status.confirmed)
+            .and_then(|status| status.block_height);
+        if let Some(current_height) = current_height.filter(|current| *current != height) {
+            if let Some(pos) = self.get_position_in_block(txid, current_height)? {
+                return Ok(Some((current_height, tx, pos)));
+            }
+        }
+
+        warn!(
+            "dropping confirmed tx {} at height {}, its position in the block is unknown",
+            txid, height
+        );
+        Ok(None)
+    }
+
+    /// looks up the position of the transaction in the block currently at
+    /// the given height, from the cache if that block was seen before
+    fn get_position_in_block(&self, txid: Txid, height: u32) -> Result<Option<usize>, Error> {
+        let block_hash = self
+            .with_retry(|client| client.get_header(height))?
+            .block_hash();
+        let cached = lock(&self.position_cache).get(&(txid, block_hash)).copied();
+        if cached.is_some() {
+            return Ok(cached);
+        }
+
+        let pos = self.with_retry(|client| client.get_position_in_block(&txid, height as usize))?;
+        if let Some(pos) = pos {
+            lock(&self.position_cache).insert((txid, block_hash), pos);
+        }
+        Ok(pos)
+    }
+
+    /// drops cached positions of the transaction, e.g. once it is
+    /// no longer watched
+    fn forget_positions(&self, txid: &Txid) {
+        lock(&self.position_cache).retain(|(cached_txid, _block_hash), _pos| cached_txid != txid);
+    }
+
+    fn augment_with_header(
+        &self,
+        height: u32,
+        tx_list: Vec<TransactionWithPosition>,
+    ) -> Result<(u32, BlockHeader, Vec<TransactionWithPosition>), Error> {
+        self.with_retry(|client| client.get_header(height))
+            .map(|header| (height, header, tx_list))
+            .map_err(Error::Bdk)
+    }
+}
+
+#[cfg(feature = "async")]
+impl<B, D> LightningWallet<B, D>
+where
+    B: Blockchain + IndexedChain + Send + 'static,
+    D: BatchDatabase + Send + 'static,
+{
+    /// same as sync but runs on tokio's blocking thread pool so it
+    /// can be awaited from an async event loop without stalling the
+    /// executor. no lock is held across an await point. a panic in sync
+    /// is resumed here, while a task cancelled by the runtime shutting
+    /// down returns Error::SyncCancelled.
+    ///
+    /// ```ignore
+    /// let mut interval = tokio::time::interval(Duration::from_secs(30));
+    /// loop {
+    ///     tokio::select! {
+    ///         _ = interval.tick() => {
+    ///             let result = ldk_wallet
+    ///                 .clone()
+    ///                 .sync_async(channel_manager.clone(), chain_monitor.clone())
+    ///                 .await;
+    ///             if let Err(e) = result {
+    ///                 eprintln!("sync failed: {}", e);
+    ///             }
+    ///         }
+    ///         _ = shutdown.recv() => break,
+    ///     }
+    /// }
+    /// ```
+    pub async fn sync_async(
+        self: Arc<Self>,
+        channel_manager: Arc<dyn Confirm + Send + Sync>,
+        chain_monitor: Arc<dyn Confirm + Send + Sync>,
+    ) -> Result<(), Error> {
+        match tokio::task::spawn_blocking(move || self.sync(channel_manager, chain_monitor)).await {
+            Ok(result) => result,
+            Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+            Err(_cancelled) => Err(Error::SyncCancelled),
+        }
+    }
+}
+
+impl<B, D> From<Wallet<B, D>> for LightningWallet<B, D>
+where
+    B: Blockchain + IndexedChain,
+    D: BatchDatabase,
+{
+    fn from(wallet: Wallet<B, D>) -> Self {
+        Self::new(wallet)
+    }
+}
+
+impl<B, D> FeeEstimator for LightningWallet<B, D>
+where
+    B: Blockchain + IndexedChain,
+    D: BatchDatabase,
+{
+    /// returns the estimated feerate in sats per 1000 weight units (sat/kw)
+    /// never returns less than ldk's FEERATE_FLOOR_SATS_PER_KW
+    /// estimates are cached for a short time so repeated calls
+    /// do not need to lock the wallet or query the backend
+    fn get_est_sat_per_1000_weight(&self, confirmation_target: ConfirmationTarget) -> u32 {
+        let fee_override = lock(&self.fee_overrides)[FeeCache::index(confirmation_target)];
+        if let Some(sats_per_1000_weight) = fee_override {
+            return sats_per_1000_weight.max(FEERATE_FLOOR_SATS_PER_KW);
+        }
+
+        let fee_source = lock(&self.fee_source).clone();
+        let from_source = fee_source.and_then(|fee_source| fee_source(confirmation_target));
+        let cached = from_source.or_else(|| lock(&self.fee_cache).get(confirmation_target));
+
+        let estimate = match cached {
+            Some(sats_per_1000_weight) => Some(sats_per_1000_weight),
+            None => match self.estimate_sat_per_1000_weight(confirmation_target) {
+                Ok(sats_per_1000_weight) => {
+                    let mut fee_cache = lock(&self.fee_cache);
+                    fee_cache.insert(confirmation_target, sats_per_1000_weight);
+                    Some(sats_per_1000_weight)
+                }
+                Err(_) => None,
+            },
+        };
+
+        let sats_per_1000_weight = match estimate {
+            Some(sats_per_1000_weight) => self.apply_fee_multiplier(sats_per_1000_weight),
+            None => lock(&self.fallback_feerates).sat_per_1000_weight(confirmation_target),
+        };
+
+        sats_per_1000_weight.max(FEERATE_FLOOR_SATS_PER_KW)
+    }
+}
+
+impl<B, D> BroadcasterInterface for LightningWallet<B, D>
+where
+    B: Blockchain + IndexedChain,
+    D: BatchDatabase,
+{
+    fn broadcast_transaction(&self, tx: &Transaction) {
+        self.broadcast_transactions(&[tx]);
+    }
+}
+
+impl<B, D> Filter for LightningWallet<B, D>
+where
+    B: Blockchain + IndexedChain,
+    D: BatchDatabase,
+{
+    fn register_tx(&self, txid: &Txid, script_pubkey: &Script) {
+        let mut filter = lock(&self.filter);
+        filter.register_tx(*txid, script_pubkey.clone());
+    }
+
+    /// if the output has already been spent in a confirmed transaction
+    /// it is returned so ldk does not have to wait for the next sync.
+    /// if the lookup fails the output is still watched so the next
+    /// sync finds the spend
+    fn register_output(&self, output: WatchedOutput) -> Option<TransactionWithPosition> {
+        let confirmed_spend = match self.get_confirmed_spend(&output) {
+            Ok(confirmed_spend) => confirmed_spend,
+            Err(e) => {
+                warn!(
+                    "failed to check whether {}:{} is spent, leaving it to the next sync: {}",
+                    output.outpoint.txid, output.outpoint.index, e
+                );
+                None
+            }
+        };
+
+        let mut filter = lock(&self.filter);
+        filter.register_output(output);
+
+        confirmed_spend.map(|(_height, tx, pos)| (pos, tx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{
+        change_wallet, confirmed_status, funded_multisig_wallet, funded_signing_wallet,
+        funded_signing_wallet_at_heights, header, regtest_wallet, signet_wallet, signing_wallet,
+        spending_tx, test_wallet, unconfirmed_status, MockChain, MockConfirm,
+    };
+    use bdk::bitcoin::hashes::hex::FromHex;
+    use bdk::bitcoin::hashes::Hash;
+    use lightning::chain::transaction::TransactionData;
+    use std::str::FromStr;
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn it_works() {
+        let result = 2 + 2;
+        assert_eq!(result, 4);
+    }
+
+    #[test]
+    fn default_fee_targets() {
+        let fee_targets = FeeTargets::default();
+        assert_eq!(fee_targets.target_blocks(ConfirmationTarget::Background), 6);
+        assert_eq!(fee_targets.target_blocks(ConfirmationTarget::Normal), 3);
+        assert_eq!(
+            fee_targets.target_blocks(ConfirmationTarget::HighPriority),
+            1
+        );
+    }
+
+    #[test]
+    fn fee_rate_conversion_does_not_truncate() {
+        let cases = [(1.0, 250), (1.5, 375), (1.9, 475), (10.25, 2563)];
+        for (sat_per_vb, sat_per_1000_weight) in cases {
+            let fee_rate = FeeRate::from_sat_per_vb(sat_per_vb);
+            assert_eq!(
+                fee_rate_to_sat_per_1000_weight(fee_rate),
+                sat_per_1000_weight
+            );
+        }
+    }
+
+    #[test]
+    fn fee_rate_conversion_uses_weight_units() {
+        let cases = [(0.25, 63), (2.5, 625), (12.0, 3000), (100.0, 25_000)];
+        for (sat_per_vb, sat_per_1000_weight) in cases {
+            let fee_rate = FeeRate::from_sat_per_vb(sat_per_vb);
+            assert_eq!(
+                fee_rate_to_sat_per_1000_weight(fee_rate),
+                sat_per_1000_weight
+            );
+            assert_eq!(fee_rate.fee_wu(1000), sat_per_1000_weight as u64);
+        }
+    }
+
+    #[test]
+    fn fallback_feerates_respect_floor() {
+        for target in [
+            ConfirmationTarget::Background,
+            ConfirmationTarget::Normal,
+            ConfirmationTarget::HighPriority,
+        ] {
+            assert!(
+                FallbackFeerates::default().sat_per_1000_weight(target)
+                    >= FEERATE_FLOOR_SATS_PER_KW
+            );
+        }
+    }
+
+    #[test]
+    fn fee_estimates_are_cached() {
+        let chain = MockChain::default();
+        chain.set_fee_rate(FeeRate::from_sat_per_vb(10.0));
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+
+        let first = wallet.get_est_sat_per_1000_weight(ConfirmationTarget::Normal);
+        let second = wallet.get_est_sat_per_1000_weight(ConfirmationTarget::Normal);
+
+        assert_eq!(first, 2500);
+        assert_eq!(second, first);
+        assert_eq!(chain.estimate_fee_calls(), 1);
+    }
+
+    #[test]
+    fn refresh_fee_cache_queries_backend() {
+        let chain = MockChain::default();
+        chain.set_fee_rate(FeeRate::from_sat_per_vb(10.0));
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+
+        wallet.get_est_sat_per_1000_weight(ConfirmationTarget::Normal);
+        chain.set_fee_rate(FeeRate::from_sat_per_vb(20.0));
+        wallet.refresh_fee_cache().unwrap();
+
+        assert_eq!(
+            wallet.get_est_sat_per_1000_weight(ConfirmationTarget::Normal),
+            5000
+        );
+        assert_eq!(chain.estimate_fee_calls(), 4);
+    }
+
+    #[test]
+    fn register_output_returns_confirmed_spend() {
+        let chain = MockChain::default();
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+
+        let script_pubkey = Script::new_v0_wpkh(&bdk::bitcoin::WPubkeyHash::hash(&[1]));
+        let outpoint = OutPoint {
+            txid: Txid::hash(&[2]),
+            index: 0,
+        };
+        let spend = spending_tx(outpoint.into_bitcoin_outpoint(), Script::new(), 1000);
+        chain.add_script_history(&script_pubkey, confirmed_status(100), spend.clone());
+        chain.set_position(spend.txid(), 3);
+
+        let confirmed_spend = wallet.register_output(WatchedOutput {
+            block_hash: None,
+            outpoint,
+            script_pubkey,
+        });
+
+        assert_eq!(confirmed_spend, Some((3, spend)));
+    }
+
+    #[test]
+    fn failed_register_output_lookup_is_retried_on_sync() {
+        let chain = MockChain::default();
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+        let channel_manager = Arc::new(MockConfirm::default());
+        let chain_monitor = Arc::new(MockConfirm::default());
+
+        let script_pubkey = Script::new_v0_wpkh(&bdk::bitcoin::WPubkeyHash::hash(&[1]));
+        let outpoint = OutPoint {
+            txid: Txid::hash(&[2]),
+            index: 0,
+        };
+        let spend = spending_tx(outpoint.into_bitcoin_outpoint(), Script::new(), 1000);
+        chain.set_height(120);
+        chain.add_script_history(&script_pubkey, confirmed_status(100), spend.clone());
+        chain.set_position(spend.txid(), 3);
+        chain.fail_script_history(&script_pubkey);
+
+        let confirmed_spend = wallet.register_output(WatchedOutput {
+            block_hash: None,
+            outpoint,
+            script_pubkey: script_pubkey.clone(),
+        });
+        assert_eq!(confirmed_spend, None);
+        assert_eq!(wallet.watched_outputs(), vec![outpoint]);
+
+        chain.restore_script_history(&script_pubkey);
+        wallet
+            .sync(channel_manager.clone(), chain_monitor.clone())
+            .unwrap();
+
+        assert_eq!(channel_manager.confirmed(), vec![(100, vec![spend.txid()])]);
+    }
+
+    #[test]
+    fn forgotten_items_are_not_scanned() {
+        let chain = MockChain::default();
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+
+        let script_pubkey = Script::new_v0_wpkh(&bdk::bitcoin::WPubkeyHash::hash(&[1]));
+        let txid = Txid::hash(&[2]);
+        let outpoint = OutPoint { txid, index: 0 };
+
+        wallet.register_tx(&txid, &script_pubkey);
+        wallet.register_output(WatchedOutput {
+            block_hash: None,
+            outpoint,
+            script_pubkey: script_pubkey.clone(),
+        });
+        wallet.register_tx(&Txid::hash(&[3]), &script_pubkey);
+        let calls_before = chain.script_history_calls();
+
+        wallet.forget_tx(&txid);
+        wallet.forget_output(&outpoint);
+        wallet
+            .get_confirmed_txs_by_block(&mut SyncErrors::new(false))
+            .unwrap();
+        assert_eq!(chain.script_history_calls(), calls_before + 1);
+
+        wallet.clear_filter();
+        wallet
+            .get_confirmed_txs_by_block(&mut SyncErrors::new(false))
+            .unwrap();
+        assert_eq!(chain.script_history_calls(), calls_before + 1);
+    }
+
+    #[test]
+    fn duplicate_registrations_are_scanned_once() {
+        let chain = MockChain::default();
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+
+        let script_pubkey = Script::new_v0_wpkh(&bdk::bitcoin::WPubkeyHash::hash(&[1]));
+        let txid = Txid::hash(&[2]);
+        for _ in 0..3 {
+            wallet.register_tx(&txid, &script_pubkey);
+        }
+
+        wallet
+            .get_confirmed_txs_by_block(&mut SyncErrors::new(false))
+            .unwrap();
+        assert_eq!(chain.script_history_calls(), 1);
+    }
+
+    #[test]
+    fn new_wallet_has_no_balance() {
+        let wallet = LightningWallet::new(test_wallet(MockChain::default()));
+        assert_eq!(wallet.get_balance().unwrap(), 0);
+    }
+
+    #[test]
+    fn wallet_is_usable_after_poisoned_lock() {
+        let wallet = LightningWallet::new(test_wallet(MockChain::default()));
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _wallet = wallet.inner.lock().unwrap();
+            panic!("poison the wallet lock");
+        }));
+        assert!(result.is_err());
+        assert!(wallet.inner.is_poisoned());
+
+        assert_eq!(wallet.get_balance().unwrap(), 0);
+        assert!(wallet.get_unused_address().is_ok());
+    }
+
+    #[test]
+    fn broadcast_transactions_broadcasts_each_tx() {
+        let chain = MockChain::default();
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+
+        let parent = spending_tx(Default::default(), Script::new(), 1000);
+        let child = spending_tx(
+            bdk::bitcoin::OutPoint::new(parent.txid(), 0),
+            Script::new(),
+            500,
+        );
+        wallet.broadcast_transactions(&[&parent, &child]);
+
+        assert_eq!(chain.broadcasts(), vec![parent.txid(), child.txid()]);
+    }
+
+    #[test]
+    fn failed_broadcast_is_recorded() {
+        let chain = MockChain::default();
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+        assert_eq!(wallet.last_broadcast_error(), None);
+
+        chain.fail_broadcasts();
+        let tx = spending_tx(Default::default(), Script::new(), 1000);
+        wallet.broadcast_transaction(&tx);
+
+        let (txid, _error) = wallet.last_broadcast_error().unwrap();
+        assert_eq!(txid, tx.txid());
+        assert!(chain.broadcasts().is_empty());
+    }
+
+    #[test]
+    fn reorged_transactions_are_unconfirmed() {
+        let chain = MockChain::default();
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+        let channel_manager = Arc::new(MockConfirm::default());
+        let chain_monitor = Arc::new(MockConfirm::default());
+
+        let script_pubkey = Script::new_v0_wpkh(&bdk::bitcoin::WPubkeyHash::hash(&[1]));
+        let tx = spending_tx(Default::default(), script_pubkey.clone(), 1000);
+        let txid = tx.txid();
+
+        chain.set_height(100);
+        chain.add_script_history(&script_pubkey, confirmed_status(100), tx.clone());
+        chain.set_tx_status(txid, Some(confirmed_status(100)));
+        chain.set_position(txid, 1);
+        wallet.register_tx(&txid, &script_pubkey);
+
+        wallet
+            .sync(channel_manager.clone(), chain_monitor.clone())
+            .unwrap();
+        assert_eq!(channel_manager.confirmed(), vec![(100, vec![txid])]);
+
+        // the block containing the tx is reorged out and ldk
+        // no longer reports the tx as relevant
+        chain.clear_script_history(&script_pubkey);
+        chain.add_script_history(&script_pubkey, unconfirmed_status(), tx);
+        chain.set_tx_status(txid, Some(unconfirmed_status()));
+
+        wallet
+            .sync(channel_manager.clone(), chain_monitor.clone())
+            .unwrap();
+        assert_eq!(channel_manager.unconfirmed(), vec![txid]);
+        assert_eq!(chain_monitor.unconfirmed(), vec![txid]);
+    }
+
+    #[test]
+    fn sync_with_stats_reports_counts() {
+        let chain = MockChain::default();
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+        let channel_manager = Arc::new(MockConfirm::default());
+        let chain_monitor = Arc::new(MockConfirm::default());
+
+        let script_pubkey = Script::new_v0_wpkh(&bdk::bitcoin::WPubkeyHash::hash(&[1]));
+        let confirmed_tx = spending_tx(Default::default(), script_pubkey.clone(), 1000);
+        let unconfirmed_txid = Txid::hash(&[2]);
+
+        chain.set_height(120);
+        chain.add_script_history(&script_pubkey, confirmed_status(110), confirmed_tx.clone());
+        chain.set_position(confirmed_tx.txid(), 0);
+        wallet.register_tx(&confirmed_tx.txid(), &script_pubkey);
+        channel_manager.set_relevant_txids(vec![unconfirmed_txid]);
+
+        let stats = wallet
+            .sync_with_stats(channel_manager.clone(), chain_monitor)
+            .unwrap();
+
+        assert_eq!(stats.checked, 1);
+        assert_eq!(stats.confirmed, 1);
+        assert_eq!(stats.unconfirmed, 1);
+        assert_eq!(stats.tip_height, 120);
+    }
+
+    #[test]
+    fn get_address_respects_index() {
+        let wallet = LightningWallet::new(test_wallet(MockChain::default()));
+
+        let first = wallet.get_address(AddressIndex::New).unwrap();
+        let second = wallet.get_address(AddressIndex::New).unwrap();
+        let peeked = wallet.get_address(AddressIndex::Peek(0)).unwrap();
+
+        assert_eq!(first.index, 0);
+        assert_eq!(second.index, 1);
+        assert_eq!(peeked.address, first.address);
+    }
+
+    #[test]
+    fn funding_spends_only_selected_utxos() {
+        let (bdk_wallet, outpoints) =
+            funded_signing_wallet(MockChain::default(), &[10_000, 20_000, 30_000]);
+        let wallet = LightningWallet::new(bdk_wallet);
+        let output_script = Script::new_v0_wsh(&bdk::bitcoin::WScriptHash::hash(&[1]));
+        let fee_rate = FeeRate::from_sat_per_vb(1.0);
+
+        let tx = wallet
+            .construct_funding_transaction_from_utxos(
+                &output_script,
+                25_000,
+                fee_rate,
+                &[outpoints[2]],
+            )
+            .unwrap();
+        let inputs = tx
+            .input
+            .iter()
+            .map(|input| input.previous_output)
+            .collect::<Vec<_>>();
+        assert_eq!(inputs, vec![outpoints[2]]);
+
+        let result = wallet.construct_funding_transaction_from_utxos(
+            &output_script,
+            25_000,
+            fee_rate,
+            &[outpoints[0]],
+        );
+        assert!(matches!(result, Err(Error::InsufficientFunds { .. })));
+    }
+
+    #[test]
+    fn drain_funding_has_no_change() {
+        let chain = MockChain::default();
+        chain.set_fee_rate(FeeRate::from_sat_per_vb(1.0));
+        let (bdk_wallet, _outpoints) = funded_signing_wallet(chain, &[10_000, 20_000]);
+        let wallet = LightningWallet::new(bdk_wallet);
+        let output_script = Script::new_v0_wsh(&bdk::bitcoin::WScriptHash::hash(&[1]));
+
+        let (tx, value) = wallet
+            .construct_funding_transaction_drain(&output_script, 6)
+            .unwrap();
+
+        assert_eq!(tx.input.len(), 2);
+        assert_eq!(tx.output.len(), 1);
+        assert_eq!(tx.output[0].script_pubkey, output_script);
+        assert_eq!(tx.output[0].value, value);
+        assert!(value < 30_000);
+    }
+
+    #[test]
+    fn insufficient_funds_counts_only_selectable_utxos() {
+        let chain = MockChain::default();
+        let (bdk_wallet, outpoints) = funded_signing_wallet_at_heights(
+            chain,
+            &[(10_000, Some(1)), (20_000, Some(1)), (50_000, None)],
+        );
+        let wallet = LightningWallet::new(bdk_wallet);
+        wallet.lock_utxo(outpoints[1]);
+        let output_script = Script::new_v0_wsh(&bdk::bitcoin::WScriptHash::hash(&[1]));
+        let needed = 30_000 + MIN_FUNDING_TX_VBYTES;
+
+        // the balance covers it but the locked and unconfirmed utxos can't be spent
+        let result = wallet.construct_funding_transaction_with_feerate(
+            &output_script,
+            30_000,
+            FeeRate::from_sat_per_vb(1.0),
+        );
+        assert!(matches!(
+            result,
+            Err(Error::InsufficientFunds { needed: n, available: 10_000 }) if n == needed
+        ));
+
+        let result = wallet.construct_funding_transaction_from_utxos(
+            &output_script,
+            30_000,
+            FeeRate::from_sat_per_vb(1.0),
+            &[outpoints[0]],
+        );
+        assert!(matches!(
+            result,
+            Err(Error::InsufficientFunds { needed: n, available: 10_000 }) if n == needed
+        ));
+    }
+
+    #[test]
+    fn drain_funding_respects_options() {
+        let chain = MockChain::default();
+        chain.set_fee_rate(FeeRate::from_sat_per_vb(1.0));
+        let (bdk_wallet, outpoints) = funded_signing_wallet_at_heights(
+            chain,
+            &[(10_000, Some(1)), (20_000, Some(1)), (40_000, None)],
+        );
+        let wallet = LightningWallet::new(bdk_wallet);
+        wallet.lock_utxo(outpoints[0]);
+        let output_script = Script::new_v0_wsh(&bdk::bitcoin::WScriptHash::hash(&[1]));
+        let options = FundingOptions {
+            fee: FundingFee::FeeRate(FeeRate::from_sat_per_vb(1.0)),
+            rbf: false,
+            ..Default::default()
+        };
+
+        let (tx, value) = wallet
+            .construct_funding_transaction_drain_with_options(&output_script, &options)
+            .unwrap();
+
+        // the locked and unconfirmed utxos are left alone
+        assert_eq!(tx.input.len(), 1);
+        assert_eq!(tx.input[0].previous_output, outpoints[1]);
+        assert!(tx.input.iter().all(|input| input.sequence >= 0xFFFFFFFE));
+        assert_eq!(tx.output.len(), 1);
+        assert_eq!(tx.output[0].value, value);
+        assert!(value < 20_000);
+    }
+
+    #[test]
+    fn each_watched_script_is_queried_once() {
+        let chain = MockChain::default();
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+
+        for i in 0..100u8 {
+            let script_pubkey = Script::new_v0_wpkh(&bdk::bitcoin::WPubkeyHash::hash(&[i % 10]));
+            wallet.register_tx(&Txid::hash(&[i]), &script_pubkey);
+        }
+
+        wallet
+            .get_confirmed_txs_by_block(&mut SyncErrors::new(false))
+            .unwrap();
+        assert_eq!(chain.script_history_calls(), 10);
+    }
+
+    #[test]
+    fn many_watched_scripts_are_queried_in_batches() {
+        let chain = MockChain::default();
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+        chain.set_height(1000);
+
+        let mut histories = HashMap::new();
+        for i in 0..1000u32 {
+            let script_pubkey =
+                Script::new_v0_wpkh(&bdk::bitcoin::WPubkeyHash::hash(&i.to_le_bytes()));
+            let tx = spending_tx(Default::default(), script_pubkey.clone(), 1000 + i as u64);
+            let height = 100 + i % 50;
+            chain.add_script_history(&script_pubkey, confirmed_status(height), tx.clone());
+            chain.set_position(tx.txid(), i as usize);
+            histories.insert(
+                script_pubkey.clone(),
+                (confirmed_status(height), tx.clone()),
+            );
+            wallet.register_tx(&tx.txid(), &script_pubkey);
+        }
+
+        let started_at = Instant::now();
+        let sequential = wallet
+            .get_confirmed_txs_by_block(&mut SyncErrors::new(false))
+            .unwrap();
+        let sequential_elapsed = started_at.elapsed();
+        assert_eq!(chain.script_history_calls(), 1000);
+
+        let batch_calls = Arc::new(AtomicUsize::new(0));
+        let calls = Arc::clone(&batch_calls);
+        wallet.set_script_batch_size(300);
+        wallet.set_batch_script_history(Box::new(move |scripts| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Ok(scripts
+                .iter()
+                .map(|script| histories.get(script).cloned().into_iter().collect())
+                .collect())
+        }));
+
+        let started_at = Instant::now();
+        let batched = wallet
+            .get_confirmed_txs_by_block(&mut SyncErrors::new(false))
+            .unwrap();
+        let batched_elapsed = started_at.elapsed();
+        println!(
+            "1000 watched scripts: sequential {:?}, batched {:?}",
+            sequential_elapsed, batched_elapsed
+        );
+
+        assert_eq!(batch_calls.load(Ordering::SeqCst), 4);
+        assert_eq!(chain.script_history_calls(), 1000);
+        assert_eq!(batched.len(), 50);
+        assert_eq!(batched, sequential);
+    }
+
+    #[test]
+    fn forgotten_output_spend_is_no_longer_checked() {
+        let chain = MockChain::default();
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+        let channel_manager = Arc::new(MockConfirm::default());
+        let chain_monitor = Arc::new(MockConfirm::default());
+
+        let script_pubkey = Script::new_v0_wpkh(&bdk::bitcoin::WPubkeyHash::hash(&[1]));
+        let outpoint = OutPoint {
+            txid: Txid::hash(&[2]),
+            index: 0,
+        };
+        let spend = spending_tx(outpoint.into_bitcoin_outpoint(), Script::new(), 1000);
+        chain.set_height(120);
+        chain.add_script_history(&script_pubkey, confirmed_status(100), spend.clone());
+        chain.set_position(spend.txid(), 0);
+        wallet.register_output(WatchedOutput {
+            block_hash: None,
+            outpoint,
+            script_pubkey,
+        });
+
+        wallet
+            .sync(channel_manager.clone(), chain_monitor.clone())
+            .unwrap();
+        let state = wallet.export_filter();
+        assert_eq!(state.confirmed_txids, vec![spend.txid()]);
+        assert_eq!(
+            state.confirmed_spends,
+            vec![(outpoint.txid, outpoint.index, spend.txid())]
+        );
+
+        wallet.forget_output(&outpoint);
+
+        assert_eq!(wallet.export_filter(), FilterState::default());
+    }
+
+    #[test]
+    fn cleared_filter_has_nothing_to_check() {
+        let chain = MockChain::default();
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+        let channel_manager = Arc::new(MockConfirm::default());
+        let chain_monitor = Arc::new(MockConfirm::default());
+
+        let script_pubkey = Script::new_v0_wpkh(&bdk::bitcoin::WPubkeyHash::hash(&[1]));
+        let tx = spending_tx(Default::default(), script_pubkey.clone(), 1000);
+        chain.set_height(120);
+        chain.add_script_history(&script_pubkey, confirmed_status(110), tx.clone());
+        chain.set_position(tx.txid(), 0);
+        wallet.register_tx(&tx.txid(), &script_pubkey);
+        wallet
+            .sync(channel_manager.clone(), chain_monitor.clone())
+            .unwrap();
+        assert_eq!(wallet.export_filter().confirmed_txids, vec![tx.txid()]);
+
+        wallet.clear_filter();
+        let tx_status_calls = chain.tx_status_calls();
+        let script_history_calls = chain.script_history_calls();
+        wallet
+            .sync(channel_manager.clone(), chain_monitor.clone())
+            .unwrap();
+
+        assert_eq!(wallet.export_filter(), FilterState::default());
+        assert_eq!(chain.tx_status_calls(), tx_status_calls);
+        assert_eq!(chain.script_history_calls(), script_history_calls);
+        assert!(channel_manager.unconfirmed().is_empty());
+    }
+
+    #[test]
+    fn filter_state_round_trips() {
+        let wallet = LightningWallet::new(test_wallet(MockChain::default()));
+
+        let script_pubkey = Script::new_v0_wpkh(&bdk::bitcoin::WPubkeyHash::hash(&[1]));
+        wallet.register_tx(&Txid::hash(&[2]), &script_pubkey);
+        wallet.register_output(WatchedOutput {
+            block_hash: None,
+            outpoint: OutPoint {
+                txid: Txid::hash(&[3]),
+                index: 1,
+            },
+            script_pubkey,
+        });
+
+        let state = wallet.export_filter();
+        let serialized = serde_json::to_string(&state).unwrap();
+        let deserialized: FilterState = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized, state);
+
+        let restored = LightningWallet::new(test_wallet(MockChain::default()));
+        restored.import_filter(deserialized);
+        assert_eq!(restored.export_filter(), state);
+    }
+
+    #[test]
+    fn derive_ldk_seed_test_vector() {
+        let wallet = LightningWallet::new(signing_wallet(MockChain::default()));
+        let seed = wallet.derive_ldk_seed().unwrap();
+        assert_eq!(
+            seed.to_vec(),
+            Vec::<u8>::from_hex("77e91d0d85a15a9bfc6200481b598a07ab39fb64ac834e9d18f3b23aeaa1a2d3")
+                .unwrap()
+        );
+
+        let watch_only = LightningWallet::new(test_wallet(MockChain::default()));
+        assert!(matches!(
+            watch_only.derive_ldk_seed(),
+            Err(Error::MissingPrivateKey)
+        ));
+    }
+
+    #[cfg(feature = "bip39")]
+    #[test]
+    fn derive_ldk_seed_from_mnemonic_test_vector() {
+        let mnemonic = bdk::keys::bip39::Mnemonic::parse(
+            "abandon abandon abandon abandon abandon abandon \
+             abandon abandon abandon abandon abandon about",
+        )
+        .unwrap();
+
+        assert_eq!(
+            derive_ldk_seed_from_mnemonic(&mnemonic, None).to_vec(),
+            Vec::<u8>::from_hex("c9e1abf64312a43d74b6452e5be41b6b430b777acaedaa7b1a67e077428bf9eb")
+                .unwrap()
+        );
+        assert_eq!(
+            derive_ldk_seed_from_mnemonic(&mnemonic, Some("TREZOR")).to_vec(),
+            Vec::<u8>::from_hex("8fc9a408eef1e3d891b5b7d9ac04c0eb11c9d1acf2e471ac3a9c363ed4971eb7")
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn get_tip_returns_current_tip() {
+        let chain = MockChain::default();
+        chain.set_height(42);
+        let wallet = LightningWallet::new(test_wallet(chain));
+
+        let (height, header) = wallet.get_tip().unwrap();
+        assert_eq!(height, 42);
+        assert_eq!(header, crate::mock::header(42));
+    }
+
+    #[test]
+    fn confirmation_depth_is_relative_to_tip() {
+        let chain = MockChain::default();
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+        let confirmed_txid = Txid::hash(&[1]);
+        let unconfirmed_txid = Txid::hash(&[2]);
+
+        chain.set_height(102);
+        chain.set_tx_status(confirmed_txid, Some(confirmed_status(101)));
+        chain.set_tx_status(unconfirmed_txid, Some(unconfirmed_status()));
+
+        assert_eq!(
+            wallet.get_confirmation_height(&confirmed_txid).unwrap(),
+            Some(101)
+        );
+        assert_eq!(wallet.confirmation_depth(&confirmed_txid).unwrap(), Some(2));
+        assert_eq!(wallet.confirmation_depth(&unconfirmed_txid).unwrap(), None);
+    }
+
+    #[test]
+    fn confirmation_depth_retries_transient_errors() {
+        let chain = MockChain::default();
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+        wallet.set_retry_config(RetryConfig {
+            max_attempts: 2,
+            base_delay: Duration::ZERO,
+        });
+        let txid = Txid::hash(&[1]);
+        chain.set_height(102);
+        chain.set_tx_status(txid, Some(confirmed_status(101)));
+
+        chain.fail_next_tx_status(1);
+        chain.fail_next_height(1);
+        assert_eq!(wallet.confirmation_depth(&txid).unwrap(), Some(2));
+    }
+
+    #[test]
+    fn send_to_address_rejects_other_networks() {
+        let wallet = LightningWallet::new(test_wallet(MockChain::default()));
+        let mainnet_address =
+            Address::from_str("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4").unwrap();
+
+        let result =
+            wallet.send_to_address(&mainnet_address, 10_000, FeeRate::from_sat_per_vb(1.0));
+
+        assert!(matches!(
+            result,
+            Err(Error::NetworkMismatch {
+                expected: Network::Testnet,
+                got: Network::Bitcoin,
+            })
+        ));
+    }
+
+    #[test]
+    fn funding_rejects_dust_and_insufficient_funds() {
+        let (bdk_wallet, _outpoints) = funded_signing_wallet(MockChain::default(), &[10_000]);
+        let wallet = LightningWallet::new(bdk_wallet);
+        let output_script = Script::new_v0_wsh(&bdk::bitcoin::WScriptHash::hash(&[1]));
+        let fee_rate = FeeRate::from_sat_per_vb(1.0);
+
+        let dust = wallet.construct_funding_transaction_with_feerate(&output_script, 100, fee_rate);
+        assert!(matches!(
+            dust,
+            Err(Error::DustValue {
+                value: 100,
+                dust_limit: 330,
+            })
+        ));
+
+        let too_much =
+            wallet.construct_funding_transaction_with_feerate(&output_script, 20_000, fee_rate);
+        assert!(matches!(
+            too_much,
+            Err(Error::InsufficientFunds {
+                available: 10_000,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn funding_rbf_is_configurable() {
+        let chain = MockChain::default();
+        chain.set_fee_rate(FeeRate::from_sat_per_vb(1.0));
+        let (bdk_wallet, _outpoints) = funded_signing_wallet(chain, &[100_000]);
+        let wallet = LightningWallet::new(bdk_wallet);
+        let output_script = Script::new_v0_wsh(&bdk::bitcoin::WScriptHash::hash(&[1]));
+
+        let rbf = wallet
+            .construct_funding_transaction_with_rbf(&output_script, 50_000, 6, true)
+            .unwrap();
+        assert!(rbf.input.iter().all(|input| input.sequence < 0xFFFFFFFE));
+
+        let no_rbf = wallet
+            .construct_funding_transaction_with_rbf(&output_script, 50_000, 6, false)
+            .unwrap();
+        assert!(no_rbf
+            .input
+            .iter()
+            .all(|input| input.sequence >= 0xFFFFFFFE));
+    }
+
+    #[test]
+    fn default_funding_options() {
+        let options = FundingOptions::default();
+        assert_eq!(options.fee, FundingFee::TargetBlocks(6));
+        assert!(options.rbf);
+        assert!(options.utxos.is_empty());
+        assert_eq!(options.min_confirmations, 1);
+        assert!(options.foreign_utxos.is_empty());
+    }
+
+    #[test]
+    fn cpfp_pays_for_the_package() {
+        let (bdk_wallet, outpoints) = funded_signing_wallet(MockChain::default(), &[50_000]);
+        let wallet = LightningWallet::new(bdk_wallet);
+        let parent = wallet
+            .list_transactions(true)
+            .unwrap()
+            .remove(0)
+            .transaction
+            .unwrap();
+        let fee_rate = FeeRate::from_sat_per_vb(5.0);
+
+        let child = wallet
+            .create_cpfp_transaction(&outpoints[0].txid, outpoints[0].vout, fee_rate)
+            .unwrap();
+
+        assert_eq!(child.input[0].previous_output, outpoints[0]);
+        let child_fee = 50_000 - child.output.iter().map(|o| o.value).sum::<u64>();
+        let package_vsize = (parent.get_weight() + child.get_weight()) as u64 / 4;
+        assert!(child_fee as f32 >= fee_rate.as_sat_vb() * package_vsize as f32);
+    }
+
+    #[test]
+    fn network_matches_wallet() {
+        let wallet = LightningWallet::new(test_wallet(MockChain::default()));
+        assert_eq!(wallet.network(), Network::Testnet);
+    }
+
+    #[test]
+    fn lightning_wallet_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<LightningWallet<MockChain, bdk::database::MemoryDatabase>>();
+    }
+
+    #[test]
+    fn sync_confirms_block_transactions_in_position_order() {
+        let chain = MockChain::default();
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+        let channel_manager = Arc::new(MockConfirm::default());
+        let chain_monitor = Arc::new(MockConfirm::default());
+
+        let script_pubkey = Script::new_v0_wpkh(&bdk::bitcoin::WPubkeyHash::hash(&[1]));
+        let first_tx = spending_tx(Default::default(), script_pubkey.clone(), 1000);
+        let second_tx = spending_tx(Default::default(), script_pubkey.clone(), 2000);
+        let third_tx = spending_tx(Default::default(), script_pubkey.clone(), 3000);
+
+        chain.set_height(120);
+        for (tx, position) in [(&third_tx, 7), (&first_tx, 1), (&second_tx, 4)] {
+            chain.add_script_history(&script_pubkey, confirmed_status(110), tx.clone());
+            chain.set_position(tx.txid(), position);
+            wallet.register_tx(&tx.txid(), &script_pubkey);
+        }
+
+        wallet
+            .sync(channel_manager.clone(), chain_monitor.clone())
+            .unwrap();
+
+        let expected = vec![(
+            110,
+            vec![first_tx.txid(), second_tx.txid(), third_tx.txid()],
+        )];
+        assert_eq!(channel_manager.confirmed(), expected);
+        assert_eq!(chain_monitor.confirmed(), expected);
+    }
+
+    #[test]
+    fn sync_confirms_blocks_in_ascending_height() {
+        let chain = MockChain::default();
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+        let channel_manager = Arc::new(MockConfirm::default());
+        let chain_monitor = Arc::new(MockConfirm::default());
+
+        let script_pubkey = Script::new_v0_wpkh(&bdk::bitcoin::WPubkeyHash::hash(&[1]));
+        let txs = [300, 200, 100]
+            .iter()
+            .map(|height| {
+                let tx = spending_tx(Default::default(), script_pubkey.clone(), *height as u64);
+                chain.add_script_history(&script_pubkey, confirmed_status(*height), tx.clone());
+                chain.set_position(tx.txid(), 0);
+                wallet.register_tx(&tx.txid(), &script_pubkey);
+                tx
+            })
+            .collect::<Vec<Transaction>>();
+        chain.set_height(300);
+
+        wallet
+            .sync(channel_manager.clone(), chain_monitor.clone())
+            .unwrap();
+
+        let expected = vec![
+            (100, vec![txs[2].txid()]),
+            (200, vec![txs[1].txid()]),
+            (300, vec![txs[0].txid()]),
+        ];
+        assert_eq!(channel_manager.confirmed(), expected);
+        assert_eq!(chain_monitor.confirmed(), expected);
+    }
+
+    #[test]
+    fn sync_skips_confirmed_tx_without_position() {
+        let chain = MockChain::default();
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+        let channel_manager = Arc::new(MockConfirm::default());
+        let chain_monitor = Arc::new(MockConfirm::default());
+
+        let script_pubkey = Script::new_v0_wpkh(&bdk::bitcoin::WPubkeyHash::hash(&[1]));
+        let tx = spending_tx(Default::default(), script_pubkey.clone(), 1000);
+
+        chain.set_height(120);
+        chain.add_script_history(&script_pubkey, confirmed_status(110), tx.clone());
+        chain.set_tx_status(tx.txid(), Some(confirmed_status(110)));
+        wallet.register_tx(&tx.txid(), &script_pubkey);
+
+        let stats = wallet
+            .sync_with_stats(channel_manager.clone(), chain_monitor.clone())
+            .unwrap();
+
+        assert_eq!(stats.confirmed, 0);
+        assert!(channel_manager.confirmed().is_empty());
+        assert!(chain_monitor.confirmed().is_empty());
+        assert_eq!(channel_manager.best_blocks(), vec![120]);
+    }
+
+    #[test]
+    fn sync_skips_confirmed_tx_without_height() {
+        let chain = MockChain::default();
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+        let channel_manager = Arc::new(MockConfirm::default());
+        let chain_monitor = Arc::new(MockConfirm::default());
+
+        let script_pubkey = Script::new_v0_wpkh(&bdk::bitcoin::WPubkeyHash::hash(&[1]));
+        let tx = spending_tx(Default::default(), script_pubkey.clone(), 1000);
+        let status = TxStatus {
+            block_height: None,
+            ..confirmed_status(110)
+        };
+
+        chain.set_height(120);
+        chain.add_script_history(&script_pubkey, status, tx.clone());
+        chain.set_position(tx.txid(), 0);
+        wallet.register_tx(&tx.txid(), &script_pubkey);
+
+        wallet
+            .sync(channel_manager.clone(), chain_monitor.clone())
+            .unwrap();
+
+        assert!(channel_manager.confirmed().is_empty());
+        assert!(chain_monitor.confirmed().is_empty());
+    }
+
+    #[test]
+    fn configured_fallback_feerates_used_when_estimation_fails() {
+        let wallet = LightningWallet::new(test_wallet(MockChain::default()));
+        wallet.set_fallback_feerates(FallbackFeerates {
+            background: FeeRate::from_sat_per_vb(2.0),
+            normal: FeeRate::from_sat_per_vb(5.0),
+            high_priority: FeeRate::from_sat_per_vb(10.0),
+        });
+
+        assert_eq!(
+            wallet.get_est_sat_per_1000_weight(ConfirmationTarget::Background),
+            500
+        );
+        assert_eq!(
+            wallet.get_est_sat_per_1000_weight(ConfirmationTarget::Normal),
+            1250
+        );
+        assert_eq!(
+            wallet.get_est_sat_per_1000_weight(ConfirmationTarget::HighPriority),
+            2500
+        );
+    }
+
+    #[test]
+    fn fee_multiplier_scales_estimates() {
+        let chain = MockChain::default();
+        chain.set_fee_rate(FeeRate::from_sat_per_vb(10.0));
+        let wallet = LightningWallet::new(test_wallet(chain));
+
+        wallet.set_fee_multiplier(1.0);
+        assert_eq!(
+            wallet.get_est_sat_per_1000_weight(ConfirmationTarget::Normal),
+            2500
+        );
+
+        wallet.set_fee_multiplier(1.5);
+        assert_eq!(
+            wallet.get_est_sat_per_1000_weight(ConfirmationTarget::Normal),
+            3750
+        );
+    }
+
+    #[test]
+    fn fee_multiplier_applies_before_floor() {
+        let chain = MockChain::default();
+        chain.set_fee_rate(FeeRate::from_sat_per_vb(1.0));
+        let wallet = LightningWallet::new(test_wallet(chain));
+
+        wallet.set_fee_multiplier(0.5);
+        assert_eq!(
+            wallet.get_est_sat_per_1000_weight(ConfirmationTarget::Normal),
+            FEERATE_FLOOR_SATS_PER_KW
+        );
+    }
+
+    #[test]
+    fn sync_passes_backend_headers_to_ldk() {
+        let chain = MockChain::default();
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+        let channel_manager = Arc::new(MockConfirm::default());
+        let chain_monitor = Arc::new(MockConfirm::default());
+
+        let script_pubkey = Script::new_v0_wpkh(&bdk::bitcoin::WPubkeyHash::hash(&[1]));
+        let tx = spending_tx(Default::default(), script_pubkey.clone(), 1000);
+        let block_header = BlockHeader {
+            nonce: 110,
+            ..header(110)
+        };
+        let tip_header = BlockHeader {
+            nonce: 120,
+            ..header(120)
+        };
+
+        chain.set_height(120);
+        chain.set_header(110, block_header);
+        chain.set_header(120, tip_header);
+        chain.add_script_history(&script_pubkey, confirmed_status(110), tx.clone());
+        chain.set_position(tx.txid(), 3);
+        wallet.register_tx(&tx.txid(), &script_pubkey);
+
+        wallet
+            .sync(channel_manager.clone(), chain_monitor.clone())
+            .unwrap();
+
+        assert_eq!(channel_manager.headers(), vec![block_header, tip_header]);
+        assert_eq!(chain_monitor.headers(), vec![block_header, tip_header]);
+        assert_eq!(channel_manager.best_blocks(), vec![120]);
+    }
+
+    #[test]
+    fn sync_without_watched_items_only_updates_best_block() {
+        let chain = MockChain::default();
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+        let channel_manager = Arc::new(MockConfirm::default());
+        let chain_monitor = Arc::new(MockConfirm::default());
+        chain.set_height(42);
+
+        wallet
+            .sync(channel_manager.clone(), chain_monitor.clone())
+            .unwrap();
+
+        assert_eq!(chain.script_history_calls(), 0);
+        assert_eq!(chain.tx_status_calls(), 0);
+        for confirm in [channel_manager, chain_monitor] {
+            assert!(confirm.confirmed().is_empty());
+            assert!(confirm.unconfirmed().is_empty());
+            assert_eq!(confirm.best_blocks(), vec![42]);
+        }
+    }
+
+    #[test]
+    fn new_with_network_rejects_other_networks() {
+        let result =
+            LightningWallet::new_with_network(test_wallet(MockChain::default()), Network::Bitcoin);
+        assert!(matches!(
+            result,
+            Err(Error::NetworkMismatch {
+                expected: Network::Bitcoin,
+                got: Network::Testnet,
+            })
+        ));
+
+        let wallet =
+            LightningWallet::new_with_network(test_wallet(MockChain::default()), Network::Testnet)
+                .unwrap();
+        assert_eq!(wallet.network(), Network::Testnet);
+    }
+
+    #[test]
+    fn sync_reports_tx_matching_txid_and_output_once() {
+        let chain = MockChain::default();
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+        let channel_manager = Arc::new(MockConfirm::default());
+        let chain_monitor = Arc::new(MockConfirm::default());
+
+        let script_pubkey = Script::new_v0_wpkh(&bdk::bitcoin::WPubkeyHash::hash(&[1]));
+        let outpoint = OutPoint {
+            txid: Txid::hash(&[2]),
+            index: 0,
+        };
+        let spend = spending_tx(outpoint.into_bitcoin_outpoint(), Script::new(), 1000);
+
+        chain.set_height(120);
+        chain.add_script_history(&script_pubkey, unconfirmed_status(), spend.clone());
+        wallet.register_tx(&spend.txid(), &script_pubkey);
+        wallet.register_output(WatchedOutput {
+            block_hash: None,
+            outpoint,
+            script_pubkey: script_pubkey.clone(),
+        });
+
+        chain.clear_script_history(&script_pubkey);
+        chain.add_script_history(&script_pubkey, confirmed_status(110), spend.clone());
+        chain.set_position(spend.txid(), 1);
+
+        wallet
+            .sync(channel_manager.clone(), chain_monitor.clone())
+            .unwrap();
+
+        assert_eq!(channel_manager.confirmed(), vec![(110, vec![spend.txid()])]);
+        assert_eq!(chain_monitor.confirmed(), vec![(110, vec![spend.txid()])]);
+    }
+
+    #[test]
+    fn is_output_spent_finds_confirmed_spend() {
+        let chain = MockChain::default();
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+
+        let script_pubkey = Script::new_v0_wsh(&bdk::bitcoin::WScriptHash::hash(&[1]));
+        let spent = BitcoinOutPoint::new(Txid::hash(&[2]), 0);
+        let unspent = BitcoinOutPoint::new(Txid::hash(&[2]), 1);
+        let spend = spending_tx(spent, Script::new(), 1000);
+        chain.add_script_history(&script_pubkey, confirmed_status(100), spend.clone());
+
+        assert_eq!(
+            wallet.is_output_spent(&script_pubkey, spent).unwrap(),
+            Some(spend.txid())
+        );
+        assert_eq!(
+            wallet.is_output_spent(&script_pubkey, unspent).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn sync_uses_batched_tx_status_lookup() {
+        let chain = MockChain::default();
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+        let channel_manager = Arc::new(MockConfirm::default());
+        let chain_monitor = Arc::new(MockConfirm::default());
+
+        let confirmed_txid = Txid::hash(&[1]);
+        let unconfirmed_txid = Txid::hash(&[2]);
+        let unknown_txid = Txid::hash(&[3]);
+        channel_manager.set_relevant_txids(vec![confirmed_txid, unconfirmed_txid, unknown_txid]);
+
+        let batch_calls = Arc::new(Mutex::new(0));
+        let batch_calls_ref = batch_calls.clone();
+        wallet.set_batch_tx_status(Box::new(move |txids| {
+            *batch_calls_ref.lock().unwrap() += 1;
+            Ok(txids
+                .iter()
+                .map(|txid| {
+                    if *txid == confirmed_txid {
+                        Some(confirmed_status(100))
+                    } else if *txid == unconfirmed_txid {
+                        Some(unconfirmed_status())
+                    } else {
+                        None
+                    }
+                })
+                .collect())
+        }));
+
+        wallet
+            .sync(channel_manager.clone(), chain_monitor.clone())
+            .unwrap();
+
+        assert_eq!(*batch_calls.lock().unwrap(), 1);
+        assert_eq!(chain.tx_status_calls(), 0);
+
+        let mut unconfirmed = channel_manager.unconfirmed();
+        unconfirmed.sort_unstable();
+        let mut expected = vec![unconfirmed_txid, unknown_txid];
+        expected.sort_unstable();
+        assert_eq!(unconfirmed, expected);
+    }
+
+    #[test]
+    fn fee_source_is_consulted_before_backend() {
+        let chain = MockChain::default();
+        chain.set_fee_rate(FeeRate::from_sat_per_vb(10.0));
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+
+        wallet.set_fee_source(Arc::new(|confirmation_target| match confirmation_target {
+            ConfirmationTarget::HighPriority => None,
+            _ => Some(1234),
+        }));
+
+        assert_eq!(
+            wallet.get_est_sat_per_1000_weight(ConfirmationTarget::Normal),
+            1234
+        );
+        assert_eq!(chain.estimate_fee_calls(), 0);
+        assert_eq!(
+            wallet.get_est_sat_per_1000_weight(ConfirmationTarget::HighPriority),
+            2500
+        );
+        assert_eq!(chain.estimate_fee_calls(), 1);
+    }
+
+    #[test]
+    fn with_wallet_exposes_inner_wallet() {
+        let wallet = LightningWallet::new(test_wallet(MockChain::default()));
+        let address = wallet.get_unused_address().unwrap();
+
+        let is_mine = wallet
+            .with_wallet(|inner| inner.is_mine(&address.script_pubkey()))
+            .unwrap();
+        assert!(is_mine);
+    }
+
+    #[test]
+    fn public_descriptor_redacts_private_keys() {
+        let wallet = LightningWallet::new(signing_wallet(MockChain::default()));
+
+        let descriptor = wallet.public_descriptor().unwrap().unwrap();
+        assert!(descriptor.starts_with("wpkh("));
+        assert!(descriptor.contains("tpub"));
+        assert!(!descriptor.contains("tprv"));
+    }
+
+    struct BlockingConfirm {
+        entered: Mutex<std::sync::mpsc::Sender<()>>,
+        release: Mutex<std::sync::mpsc::Receiver<()>>,
+    }
+
+    impl Confirm for BlockingConfirm {
+        fn transactions_confirmed(&self, _: &BlockHeader, _: &TransactionData, _: u32) {}
+        fn transaction_unconfirmed(&self, _: &Txid) {}
+        fn best_block_updated(&self, _: &BlockHeader, _: u32) {}
+
+        fn get_relevant_txids(&self) -> Vec<Txid> {
+            self.entered.lock().unwrap().send(()).unwrap();
+            self.release.lock().unwrap().recv().unwrap();
+            vec![]
+        }
+    }
+
+    #[test]
+    fn overlapping_sync_returns_sync_in_progress() {
+        let wallet = Arc::new(LightningWallet::new(test_wallet(MockChain::default())));
+        let (entered_tx, entered_rx) = std::sync::mpsc::channel();
+        let (release_tx, release_rx) = std::sync::mpsc::channel();
+        let blocking = Arc::new(BlockingConfirm {
+            entered: Mutex::new(entered_tx),
+            release: Mutex::new(release_rx),
+        });
+
+        let first_wallet = wallet.clone();
+        let first_sync = std::thread::spawn(move || {
+            first_wallet.sync(blocking, Arc::new(MockConfirm::default()))
+        });
+        entered_rx.recv().unwrap();
+
+        let second = wallet.sync(
+            Arc::new(MockConfirm::default()),
+            Arc::new(MockConfirm::default()),
+        );
+        assert!(matches!(second, Err(Error::SyncInProgress)));
+
+        release_tx.send(()).unwrap();
+        first_sync.join().unwrap().unwrap();
+
+        wallet
+            .sync(
+                Arc::new(MockConfirm::default()),
+                Arc::new(MockConfirm::default()),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn estimate_funding_fee_matches_built_transaction() {
+        let chain = MockChain::default();
+        chain.set_fee_rate(FeeRate::from_sat_per_vb(5.0));
+        let (bdk_wallet, _outpoints) = funded_signing_wallet(chain, &[100_000]);
+        let wallet = LightningWallet::new(bdk_wallet);
+        let output_script = Script::new_v0_wsh(&bdk::bitcoin::WScriptHash::hash(&[1]));
+
+        let estimated_fee = wallet
+            .estimate_funding_fee(&output_script, 50_000, 6)
+            .unwrap();
+        assert_eq!(wallet.get_address(AddressIndex::New).unwrap().index, 0);
+
+        let tx = wallet
+            .construct_funding_transaction(&output_script, 50_000, 6)
+            .unwrap();
+        let output_value = tx.output.iter().map(|output| output.value).sum::<u64>();
+        assert_eq!(estimated_fee, 100_000 - output_value);
+    }
+
+    #[test]
+    fn funding_attaches_op_return_data() {
+        let (bdk_wallet, _outpoints) = funded_signing_wallet(MockChain::default(), &[100_000]);
+        let wallet = LightningWallet::new(bdk_wallet);
+        let output_script = Script::new_v0_wsh(&bdk::bitcoin::WScriptHash::hash(&[1]));
+
+        let options = FundingOptions {
+            fee: FundingFee::FeeRate(FeeRate::from_sat_per_vb(1.0)),
+            data: Some(vec![7; 32]),
+            ..Default::default()
+        };
+        let tx = wallet
+            .construct_funding_transaction_with_options(&output_script, 50_000, &options)
+            .unwrap();
+        assert!(tx
+            .output
+            .iter()
+            .any(|output| output.script_pubkey.is_op_return() && output.value == 0));
+
+        let options = FundingOptions {
+            data: Some(vec![7; MAX_OP_RETURN_DATA_LEN + 1]),
+            ..options
+        };
+        let result =
+            wallet.construct_funding_transaction_with_options(&output_script, 50_000, &options);
+        assert!(matches!(
+            result,
+            Err(Error::DataTooLarge { len: 81, max: 80 })
+        ));
+    }
+
+    #[test]
+    fn funding_skips_locked_utxos() {
+        let (bdk_wallet, outpoints) =
+            funded_signing_wallet(MockChain::default(), &[60_000, 60_000]);
+        let wallet = LightningWallet::new(bdk_wallet);
+        let output_script = Script::new_v0_wsh(&bdk::bitcoin::WScriptHash::hash(&[1]));
+        let fee_rate = FeeRate::from_sat_per_vb(1.0);
+
+        let first = wallet
+            .construct_funding_transaction_with_feerate(&output_script, 50_000, fee_rate)
+            .unwrap();
+        assert_eq!(first.input.len(), 1);
+        wallet.lock_utxo(first.input[0].previous_output);
+
+        let second = wallet
+            .construct_funding_transaction_with_feerate(&output_script, 50_000, fee_rate)
+            .unwrap();
+        assert_eq!(second.input.len(), 1);
+        assert_ne!(
+            second.input[0].previous_output,
+            first.input[0].previous_output
+        );
+
+        for outpoint in &outpoints {
+            wallet.lock_utxo(*outpoint);
+        }
+        assert!(wallet
+            .construct_funding_transaction_with_feerate(&output_script, 50_000, fee_rate)
+            .is_err());
+
+        wallet.unlock_utxo(&outpoints[0]);
+        wallet
+            .construct_funding_transaction_with_feerate(&output_script, 50_000, fee_rate)
+            .unwrap();
+    }
+
+    #[test]
+    fn get_confirmed_relevant_txs_includes_block_details() {
+        let chain = MockChain::default();
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+
+        let confirmed_txid = Txid::hash(&[1]);
+        let unconfirmed_txid = Txid::hash(&[2]);
+        let block_header = BlockHeader {
+            time: 1_600_000_000,
+            ..header(100)
+        };
+        chain.set_header(100, block_header);
+        chain.set_tx_status(confirmed_txid, Some(confirmed_status(100)));
+        chain.set_tx_status(unconfirmed_txid, Some(unconfirmed_status()));
+
+        let confirmed = wallet
+            .get_confirmed_relevant_txs(&[confirmed_txid, unconfirmed_txid, Txid::hash(&[3])])
+            .unwrap();
+        assert_eq!(
+            confirmed,
+            vec![(
+                confirmed_txid,
+                100,
+                block_header.block_hash(),
+                1_600_000_000
+            )]
+        );
+    }
+
+    #[test]
+    fn best_effort_sync_skips_failing_script() {
+        let chain = MockChain::default();
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+        let channel_manager = Arc::new(MockConfirm::default());
+        let chain_monitor = Arc::new(MockConfirm::default());
+
+        let failing_script = Script::new_v0_wpkh(&bdk::bitcoin::WPubkeyHash::hash(&[1]));
+        let working_script = Script::new_v0_wpkh(&bdk::bitcoin::WPubkeyHash::hash(&[2]));
+        chain.set_height(120);
+        for script in [&failing_script, &working_script] {
+            let tx = spending_tx(Default::default(), script.clone(), 1000);
+            chain.add_script_history(script, confirmed_status(110), tx.clone());
+            chain.set_position(tx.txid(), 0);
+            wallet.register_tx(&tx.txid(), script);
+        }
+        let working_txid = spending_tx(Default::default(), working_script.clone(), 1000).txid();
+        chain.fail_script_history(&failing_script);
+        wallet.set_retry_config(RetryConfig {
+            max_attempts: 3,
+            base_delay: Duration::ZERO,
+        });
+
+        assert!(wallet
+            .sync(channel_manager.clone(), chain_monitor.clone())
+            .is_err());
+        assert!(channel_manager.confirmed().is_empty());
+
+        let (stats, errors) = wallet
+            .sync_best_effort(channel_manager.clone(), chain_monitor.clone())
+            .unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(stats.confirmed, 1);
+        assert_eq!(channel_manager.confirmed(), vec![(110, vec![working_txid])]);
+        assert_eq!(channel_manager.best_blocks(), vec![120]);
+    }
+
+    #[test]
+    fn sync_retries_transient_backend_errors() {
+        let chain = MockChain::default();
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+        let channel_manager = Arc::new(MockConfirm::default());
+        let chain_monitor = Arc::new(MockConfirm::default());
+
+        let txid = Txid::hash(&[1]);
+        chain.set_tx_status(txid, Some(unconfirmed_status()));
+        channel_manager.set_relevant_txids(vec![txid]);
+        wallet.set_retry_config(RetryConfig {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(1),
+        });
+
+        chain.fail_next_tx_status(2);
+        wallet
+            .sync(channel_manager.clone(), chain_monitor.clone())
+            .unwrap();
+        assert_eq!(channel_manager.unconfirmed(), vec![txid]);
+        assert_eq!(chain.tx_status_calls(), 3);
+
+        chain.fail_next_tx_status(3);
+        assert!(wallet.sync(channel_manager, chain_monitor).is_err());
+        assert_eq!(chain.tx_status_calls(), 6);
+    }
+
+    #[test]
+    fn permanent_backend_errors_are_not_retried() {
+        let chain = MockChain::default();
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+        wallet.set_retry_config(RetryConfig {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(1),
+        });
+
+        chain.fail_next_tx_status_permanently(1);
+
+        assert!(wallet.is_confirmed(&Txid::hash(&[1])).is_err());
+        assert_eq!(chain.tx_status_calls(), 1);
+    }
+
+    #[test]
+    fn retry_backoff_does_not_hold_the_wallet_lock() {
+        let chain = MockChain::default();
+        let wallet = Arc::new(LightningWallet::new(test_wallet(chain.clone())));
+        let txid = Txid::hash(&[1]);
+        chain.set_tx_status(txid, Some(unconfirmed_status()));
+        wallet.set_retry_config(RetryConfig {
+            max_attempts: 2,
+            base_delay: Duration::from_millis(500),
+        });
+        chain.fail_next_tx_status(1);
+
+        let retrying = Arc::clone(&wallet);
+        let handle = std::thread::spawn(move || retrying.is_confirmed(&txid));
+        while chain.tx_status_calls() == 0 {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        // the first attempt failed so the other thread is backing off
+        let started_at = Instant::now();
+        wallet.get_balance().unwrap();
+        assert!(started_at.elapsed() < Duration::from_millis(250));
+
+        assert!(!handle.join().unwrap().unwrap());
+        assert_eq!(chain.tx_status_calls(), 2);
+    }
+
+    #[test]
+    fn register_and_scan_output_returns_spend_with_height() {
+        let chain = MockChain::default();
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+
+        let script_pubkey = Script::new_v0_wsh(&bdk::bitcoin::WScriptHash::hash(&[1]));
+        let outpoint = OutPoint {
+            txid: Txid::hash(&[2]),
+            index: 0,
+        };
+        let spend = spending_tx(outpoint.into_bitcoin_outpoint(), Script::new(), 1000);
+        chain.add_script_history(&script_pubkey, confirmed_status(100), spend.clone());
+        chain.set_position(spend.txid(), 5);
+
+        let confirmed_spend = wallet
+            .register_and_scan_output(WatchedOutput {
+                block_hash: None,
+                outpoint,
+                script_pubkey,
+            })
+            .unwrap();
+
+        assert_eq!(confirmed_spend, Some((100, spend, 5)));
+        assert_eq!(wallet.export_filter().watched_outputs.len(), 1);
+    }
+
+    #[test]
+    fn batch_funding_returns_vout_of_each_channel() {
+        let chain = MockChain::default();
+        chain.set_fee_rate(FeeRate::from_sat_per_vb(1.0));
+        let (bdk_wallet, _outpoints) = funded_signing_wallet(chain, &[200_000]);
+        let wallet = LightningWallet::new(bdk_wallet);
+
+        let outputs = (1..=3u8)
+            .map(|i| {
+                let script = Script::new_v0_wsh(&bdk::bitcoin::WScriptHash::hash(&[i]));
+                (script, 20_000 * i as u64)
+            })
+            .collect::<Vec<(Script, u64)>>();
+
+        let (tx, outpoints) = wallet
+            .construct_batch_funding_transaction(&outputs, 6)
+            .unwrap();
+
+        assert_eq!(outpoints.len(), 3);
+        for ((script, value), outpoint) in outputs.iter().zip(&outpoints) {
+            assert_eq!(outpoint.txid, tx.txid());
+            let output = &tx.output[outpoint.index as usize];
+            assert_eq!(&output.script_pubkey, script);
+            assert_eq!(output.value, *value);
+        }
+    }
+
+    #[test]
+    fn batch_funding_to_the_same_script_gets_distinct_outpoints() {
+        let chain = MockChain::default();
+        chain.set_fee_rate(FeeRate::from_sat_per_vb(1.0));
+        let (bdk_wallet, _outpoints) = funded_signing_wallet(chain, &[200_000]);
+        let wallet = LightningWallet::new(bdk_wallet);
+        let script = Script::new_v0_wsh(&bdk::bitcoin::WScriptHash::hash(&[1]));
+        let outputs = vec![(script.clone(), 30_000), (script, 30_000)];
+
+        let (tx, outpoints) = wallet
+            .construct_batch_funding_transaction(&outputs, 6)
+            .unwrap();
+
+        assert_eq!(outpoints.len(), 2);
+        assert_ne!(outpoints[0], outpoints[1]);
+    }
+
+    #[test]
+    fn funding_outpoints_must_all_be_found() {
+        let script = Script::new_v0_wsh(&bdk::bitcoin::WScriptHash::hash(&[1]));
+        let tx = spending_tx(Default::default(), script.clone(), 30_000);
+
+        assert!(matches!(
+            find_funding_outpoints(&tx, &[(script.clone(), 30_000), (script, 30_000)]),
+            Err(Error::FundingOutputMismatch { matches: 0 })
+        ));
+    }
+
+    #[test]
+    fn last_funding_feerate_matches_estimate() {
+        let chain = MockChain::default();
+        chain.set_fee_rate(FeeRate::from_sat_per_vb(7.5));
+        let (bdk_wallet, _outpoints) = funded_signing_wallet(chain, &[100_000]);
+        let wallet = LightningWallet::new(bdk_wallet);
+        let output_script = Script::new_v0_wsh(&bdk::bitcoin::WScriptHash::hash(&[1]));
+
+        assert_eq!(wallet.last_funding_feerate(), None);
+        wallet
+            .estimate_funding_fee(&output_script, 50_000, 6)
+            .unwrap();
+        assert_eq!(wallet.last_funding_feerate(), None);
+
+        wallet
+            .construct_funding_transaction(&output_script, 50_000, 6)
+            .unwrap();
+        assert_eq!(
+            wallet.last_funding_feerate(),
+            Some(FeeRate::from_sat_per_vb(7.5))
+        );
+    }
+
+    #[test]
+    fn get_tip_refetches_when_tip_moves() {
+        let chain = MockChain::default();
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+        chain.set_height(101);
+        chain.queue_heights(vec![100]);
+
+        let (tip_height, tip_header) = wallet.get_tip().unwrap();
+
+        assert_eq!(tip_height, 101);
+        assert_eq!(tip_header, header(101));
+    }
+
+    #[test]
+    fn unsigned_funding_psbt_round_trips_through_external_signer() {
+        let chain = MockChain::default();
+        chain.set_fee_rate(FeeRate::from_sat_per_vb(1.0));
+        let (bdk_wallet, _outpoints) = funded_signing_wallet(chain, &[100_000]);
+        let wallet = LightningWallet::new(bdk_wallet);
+        let output_script = Script::new_v0_wsh(&bdk::bitcoin::WScriptHash::hash(&[1]));
+
+        let mut psbt = wallet
+            .construct_unsigned_funding_psbt(&output_script, 50_000, 6)
+            .unwrap();
+        assert!(psbt
+            .inputs
+            .iter()
+            .all(|input| input.partial_sigs.is_empty()));
+        assert!(wallet.finalize_signed_psbt(psbt.clone()).is_err());
+
+        let external_signer = signing_wallet(MockChain::default());
+        external_signer
+            .sign(&mut psbt, SignOptions::default())
+            .unwrap();
+
+        let tx = wallet.finalize_signed_psbt(psbt).unwrap();
+        assert!(tx.input.iter().all(|input| !input.witness.is_empty()));
+        assert!(tx
+            .output
+            .iter()
+            .any(|output| output.script_pubkey == output_script && output.value == 50_000));
+    }
+
+    #[test]
+    fn one_arc_serves_all_ldk_traits() {
+        let wallet = LightningWallet::new_arc(test_wallet(MockChain::default()));
+
+        let _fee_estimator: Arc<dyn FeeEstimator> = Arc::clone(&wallet) as _;
+        let _broadcaster: Arc<dyn BroadcasterInterface> = Arc::clone(&wallet) as _;
+        let _filter: Arc<dyn Filter> = Arc::clone(&wallet) as _;
+        assert_eq!(Arc::strong_count(&wallet), 4);
+    }
+
+    fn static_output(index: u16, value: u64, script_pubkey: &Script) -> SpendableOutputDescriptor {
+        SpendableOutputDescriptor::StaticOutput {
+            outpoint: OutPoint {
+                txid: Txid::hash(&[9]),
+                index,
+            },
+            output: bdk::bitcoin::TxOut {
+                value,
+                script_pubkey: script_pubkey.clone(),
+            },
+        }
+    }
+
+    #[test]
+    fn sweep_skips_outputs_below_min_value() {
+        // the outputs pay to a script of the wallet so it can sign for them
+        let (bdk_wallet, _outpoints) = funded_signing_wallet(MockChain::default(), &[1_000]);
+        let wallet = LightningWallet::new(bdk_wallet);
+        let script_pubkey = wallet
+            .get_address(AddressIndex::Peek(0))
+            .unwrap()
+            .address
+            .script_pubkey();
+        let descriptors = vec![
+            static_output(0, 50_000, &script_pubkey),
+            static_output(1, 300, &script_pubkey),
+        ];
+
+        let tx = wallet
+            .create_sweep_transaction_with_min_value(
+                &descriptors,
+                FeeRate::from_sat_per_vb(1.0),
+                1_000,
+            )
+            .unwrap();
+
+        assert_eq!(tx.input.len(), 1);
+        assert_eq!(tx.input[0].previous_output.vout, 0);
+        assert_eq!(tx.output.len(), 1);
+    }
+
+    #[test]
+    fn sweep_of_dust_is_uneconomical() {
+        let wallet = LightningWallet::new(test_wallet(MockChain::default()));
+        let script_pubkey = Script::new_v0_wpkh(&bdk::bitcoin::WPubkeyHash::hash(&[9]));
+        let descriptors = vec![
+            static_output(0, 300, &script_pubkey),
+            static_output(1, 400, &script_pubkey),
+        ];
+
+        let result = wallet.create_sweep_transaction(&descriptors, FeeRate::from_sat_per_vb(10.0));
+
+        assert!(matches!(
+            result,
+            Err(Error::UneconomicalSweep {
+                value: 700,
+                fee: 1800
+            })
+        ));
+    }
+
+    #[test]
+    fn fee_overrides_take_precedence_until_cleared() {
+        let chain = MockChain::default();
+        chain.set_fee_rate(FeeRate::from_sat_per_vb(10.0));
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+
+        wallet.override_feerate(ConfirmationTarget::Normal, 1000);
+        wallet.override_feerate(ConfirmationTarget::Background, 100);
+        assert_eq!(
+            wallet.get_est_sat_per_1000_weight(ConfirmationTarget::Normal),
+            1000
+        );
+        assert_eq!(
+            wallet.get_est_sat_per_1000_weight(ConfirmationTarget::Background),
+            FEERATE_FLOOR_SATS_PER_KW
+        );
+        assert_eq!(chain.estimate_fee_calls(), 0);
+
+        wallet.clear_overrides();
+        assert_eq!(
+            wallet.get_est_sat_per_1000_weight(ConfirmationTarget::Normal),
+            2500
+        );
+    }
+
+    #[test]
+    fn sync_caches_positions_of_confirmed_transactions() {
+        let chain = MockChain::default();
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+        let channel_manager = Arc::new(MockConfirm::default());
+        let chain_monitor = Arc::new(MockConfirm::default());
+
+        let script_pubkey = Script::new_v0_wpkh(&bdk::bitcoin::WPubkeyHash::hash(&[1]));
+        let tx = spending_tx(Default::default(), script_pubkey.clone(), 1000);
+        chain.set_height(120);
+        chain.add_script_history(&script_pubkey, confirmed_status(110), tx.clone());
+        chain.set_tx_status(tx.txid(), Some(confirmed_status(110)));
+        chain.set_position(tx.txid(), 2);
+        wallet.register_tx(&tx.txid(), &script_pubkey);
+
+        for _ in 0..2 {
+            wallet
+                .sync(channel_manager.clone(), chain_monitor.clone())
+                .unwrap();
+        }
+
+        assert_eq!(chain.position_calls(), 1);
+        assert_eq!(
+            channel_manager.confirmed(),
+            vec![(110, vec![tx.txid()]), (110, vec![tx.txid()])]
+        );
+    }
+
+    #[test]
+    fn sync_errors_name_the_failing_half() {
+        let chain = MockChain::default();
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+        let channel_manager = Arc::new(MockConfirm::default());
+        let chain_monitor = Arc::new(MockConfirm::default());
+
+        chain.fail_wallet_sync();
+        match wallet.sync(channel_manager.clone(), chain_monitor.clone()) {
+            Err(Error::OnchainSync(_)) => {}
+            result => panic!("expected an onchain sync error, got {:?}", result),
+        }
+
+        let chain = MockChain::default();
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+        let script_pubkey = Script::new_v0_wpkh(&bdk::bitcoin::WPubkeyHash::hash(&[1]));
+        wallet.register_tx(&Txid::hash(&[1]), &script_pubkey);
+        chain.fail_script_history(&script_pubkey);
+        match wallet.sync(channel_manager.clone(), chain_monitor.clone()) {
+            Err(Error::LightningSync(_)) => {}
+            result => panic!("expected a lightning sync error, got {:?}", result),
+        }
+
+        let (_stats, errors) = wallet
+            .sync_best_effort(channel_manager, chain_monitor)
+            .unwrap();
+        assert!(matches!(errors.as_slice(), [Error::LightningSync(_)]));
+    }
+
+    #[test]
+    fn sync_to_height_replays_up_to_target() {
+        let chain = MockChain::default();
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+        let channel_manager = Arc::new(MockConfirm::default());
+        let chain_monitor = Arc::new(MockConfirm::default());
+
+        chain.set_height(120);
+        let mut txids = vec![];
+        for (index, height) in [105, 115].iter().enumerate() {
+            let script_pubkey =
+                Script::new_v0_wpkh(&bdk::bitcoin::WPubkeyHash::hash(&[index as u8]));
+            let tx = spending_tx(Default::default(), script_pubkey.clone(), 1000);
+            chain.add_script_history(&script_pubkey, confirmed_status(*height), tx.clone());
+            chain.set_position(tx.txid(), 0);
+            wallet.register_tx(&tx.txid(), &script_pubkey);
+            txids.push(tx.txid());
+        }
+
+        let stats = wallet
+            .sync_to_height(channel_manager.clone(), chain_monitor.clone(), 110)
+            .unwrap();
+
+        assert_eq!(stats.tip_height, 110);
+        assert_eq!(stats.confirmed, 1);
+        assert_eq!(channel_manager.confirmed(), vec![(105, vec![txids[0]])]);
+        assert_eq!(channel_manager.best_blocks(), vec![110]);
+        assert_eq!(channel_manager.headers().last(), Some(&header(110)));
+
+        assert!(matches!(
+            wallet.sync_to_height(channel_manager, chain_monitor, 121),
+            Err(Error::HeightAboveTip {
+                height: 121,
+                tip: 120
+            })
+        ));
+    }
+
+    #[test]
+    fn sync_records_last_sync_time() {
+        let wallet = LightningWallet::new(test_wallet(MockChain::default()));
+        assert_eq!(wallet.last_sync(), None);
+        assert!(wallet.is_stale(Duration::from_secs(60)));
+
+        let before = Instant::now();
+        wallet.sync_onchain_wallet().unwrap();
+
+        let last_sync = wallet.last_sync().unwrap();
+        assert!(last_sync >= before);
+        assert!(!wallet.is_stale(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn watched_accessors_reflect_filter() {
+        let wallet = LightningWallet::new(test_wallet(MockChain::default()));
+        let txid = Txid::hash(&[1]);
+        let script_pubkey = Script::new_v0_wpkh(&bdk::bitcoin::WPubkeyHash::hash(&[1]));
+        let outpoint = OutPoint {
+            txid: Txid::hash(&[2]),
+            index: 1,
+        };
+
+        wallet.register_tx(&txid, &script_pubkey);
+        wallet.register_output(WatchedOutput {
+            block_hash: None,
+            outpoint,
+            script_pubkey: script_pubkey.clone(),
+        });
+
+        assert_eq!(wallet.watched_transactions(), vec![(txid, script_pubkey)]);
+        assert_eq!(wallet.watched_outputs(), vec![outpoint]);
+
+        wallet.forget_output(&outpoint);
+        assert!(wallet.watched_outputs().is_empty());
+    }
+
+    #[test]
+    fn estimate_feerate_for_arbitrary_block_targets() {
+        let chain = MockChain::default();
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+        chain.set_fee_rate(FeeRate::from_sat_per_vb(4.0));
+        chain.set_fee_rate_for_target(2, FeeRate::from_sat_per_vb(20.0));
+        chain.set_fee_rate_for_target(144, FeeRate::from_sat_per_vb(0.5));
+
+        assert_eq!(wallet.estimate_feerate_for_blocks(2).unwrap(), 5000);
+        assert_eq!(wallet.estimate_feerate_for_blocks(12).unwrap(), 1000);
+        assert_eq!(
+            wallet.estimate_feerate_for_blocks(144).unwrap(),
+            FEERATE_FLOOR_SATS_PER_KW
+        );
+
+        wallet.set_fee_multiplier(1.5);
+        assert_eq!(wallet.estimate_feerate_for_blocks(12).unwrap(), 1500);
+    }
+
+    #[test]
+    fn create_channel_funding_returns_funding_outpoint() {
+        let chain = MockChain::default();
+        chain.set_fee_rate(FeeRate::from_sat_per_vb(1.0));
+        let (bdk_wallet, _outpoints) = funded_signing_wallet(chain, &[100_000]);
+        let wallet = LightningWallet::new(bdk_wallet);
+        let output_script = Script::new_v0_wsh(&bdk::bitcoin::WScriptHash::hash(&[1]));
+
+        let (tx, funding_outpoint) = wallet
+            .create_channel_funding(&output_script, 50_000, 6)
+            .unwrap();
+
+        assert_eq!(funding_outpoint.txid, tx.txid());
+        let funding_output = &tx.output[funding_outpoint.index as usize];
+        assert_eq!(funding_output.script_pubkey, output_script);
+        assert_eq!(funding_output.value, 50_000);
+    }
+
+    #[test]
+    fn funding_outpoint_requires_exactly_one_match() {
+        let output_script = Script::new_v0_wsh(&bdk::bitcoin::WScriptHash::hash(&[1]));
+        let mut tx = spending_tx(Default::default(), Script::new(), 1000);
+
+        assert!(matches!(
+            find_funding_outpoint(&tx, &output_script),
+            Err(Error::FundingOutputMismatch { matches: 0 })
+        ));
+
+        for _ in 0..2 {
+            tx.output.push(bdk::bitcoin::TxOut {
+                value: 50_000,
+                script_pubkey: output_script.clone(),
+            });
+        }
+        assert!(matches!(
+            find_funding_outpoint(&tx, &output_script),
+            Err(Error::FundingOutputMismatch { matches: 2 })
+        ));
+    }
+
+    #[test]
+    fn funding_change_follows_change_policy() {
+        let chain = MockChain::default();
+        chain.set_fee_rate(FeeRate::from_sat_per_vb(1.0));
+        let (bdk_wallet, _outpoints) = funded_signing_wallet(chain, &[100_000]);
+        let wallet = LightningWallet::new(bdk_wallet);
+        let output_script = Script::new_v0_wsh(&bdk::bitcoin::WScriptHash::hash(&[1]));
+        let change_address = Address::from_script(
+            &Script::new_v0_wpkh(&bdk::bitcoin::WPubkeyHash::hash(&[2])),
+            Network::Testnet,
+        )
+        .unwrap();
+
+        let tx = wallet
+            .construct_funding_transaction_with_options(
+                &output_script,
+                50_000,
+                &FundingOptions::default(),
+            )
+            .unwrap();
+        let change = tx
+            .output
+            .iter()
+            .find(|output| output.script_pubkey != output_script)
+            .unwrap();
+        assert!(wallet
+            .with_wallet(|wallet| wallet.is_mine(&change.script_pubkey))
+            .unwrap());
+
+        let options = FundingOptions {
+            change_policy: ChangePolicy::DrainTo(change_address.clone()),
+            ..Default::default()
+        };
+        let tx = wallet
+            .construct_funding_transaction_with_options(&output_script, 50_000, &options)
+            .unwrap();
+        assert_eq!(tx.output.len(), 2);
+        assert!(tx
+            .output
+            .iter()
+            .any(|output| output.script_pubkey == change_address.script_pubkey()));
+
+        let mainnet_address =
+            Address::from_script(&change_address.script_pubkey(), Network::Bitcoin).unwrap();
+        let options = FundingOptions {
+            change_policy: ChangePolicy::DrainTo(mainnet_address),
+            ..Default::default()
+        };
+        assert!(matches!(
+            wallet.construct_funding_transaction_with_options(&output_script, 50_000, &options),
+            Err(Error::NetworkMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn reconcile_relevant_reports_confirmation_heights() {
+        let chain = MockChain::default();
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+        let confirmed_txid = Txid::hash(&[1]);
+        let unconfirmed_txid = Txid::hash(&[2]);
+        let unknown_txid = Txid::hash(&[3]);
+        chain.set_tx_status(confirmed_txid, Some(confirmed_status(101)));
+        chain.set_tx_status(unconfirmed_txid, Some(unconfirmed_status()));
+
+        let reconciled = wallet
+            .reconcile_relevant(&[confirmed_txid, unconfirmed_txid, unknown_txid])
+            .unwrap();
+
+        assert_eq!(
+            reconciled,
+            vec![
+                (confirmed_txid, Some(101)),
+                (unconfirmed_txid, None),
+                (unknown_txid, None)
+            ]
+        );
+    }
+
+    #[test]
+    fn always_unspendable_utxos_are_never_selected() {
+        let (bdk_wallet, outpoints) =
+            funded_signing_wallet(MockChain::default(), &[60_000, 60_000]);
+        let wallet = LightningWallet::new(bdk_wallet);
+        let output_script = Script::new_v0_wsh(&bdk::bitcoin::WScriptHash::hash(&[1]));
+        let fee_rate = FeeRate::from_sat_per_vb(1.0);
+        wallet.set_always_unspendable(&[outpoints[0]]);
+
+        for _ in 0..3 {
+            let tx = wallet
+                .construct_funding_transaction_with_feerate(&output_script, 50_000, fee_rate)
+                .unwrap();
+            assert_eq!(tx.input.len(), 1);
+            assert_eq!(tx.input[0].previous_output, outpoints[1]);
+        }
+
+        wallet.sync_onchain_wallet().unwrap();
+        wallet.lock_utxo(outpoints[1]);
+        assert!(wallet
+            .construct_funding_transaction_with_feerate(&output_script, 50_000, fee_rate)
+            .is_err());
+
+        wallet.set_always_unspendable(&[]);
+        let tx = wallet
+            .construct_funding_transaction_with_feerate(&output_script, 50_000, fee_rate)
+            .unwrap();
+        assert_eq!(tx.input[0].previous_output, outpoints[0]);
+    }
+
+    #[test]
+    fn swap_wallet_switches_backends() {
+        let first_chain = MockChain::default();
+        let second_chain = MockChain::default();
+        first_chain.set_height(100);
+        second_chain.set_height(200);
+        let wallet = LightningWallet::new(test_wallet(first_chain.clone()));
+        let channel_manager = Arc::new(MockConfirm::default());
+        let chain_monitor = Arc::new(MockConfirm::default());
+
+        wallet
+            .sync(channel_manager.clone(), chain_monitor.clone())
+            .unwrap();
+        let old_wallet = wallet.swap_wallet(test_wallet(second_chain)).unwrap();
+        wallet
+            .sync(channel_manager.clone(), chain_monitor.clone())
+            .unwrap();
+
+        assert_eq!(old_wallet.client().get_height().unwrap(), 100);
+        assert_eq!(channel_manager.best_blocks(), vec![100, 200]);
+
+        assert!(matches!(
+            wallet.swap_wallet(signing_wallet(first_chain)),
+            Err(Error::DescriptorMismatch)
+        ));
+    }
+
+    #[test]
+    fn dust_limit_depends_on_script_type() {
+        let wallet = LightningWallet::new(test_wallet(MockChain::default()));
+        let dust_relay_fee = FeeRate::from_sat_per_vb(3.0);
+
+        let p2wpkh = Script::new_v0_wpkh(&bdk::bitcoin::WPubkeyHash::hash(&[1]));
+        let p2tr = Script::from_hex(
+            "51200000000000000000000000000000000000000000000000000000000000000000",
+        )
+        .unwrap();
+        let p2sh = Script::new_p2sh(&bdk::bitcoin::ScriptHash::hash(&[1]));
+
+        assert_eq!(wallet.dust_limit(&p2wpkh, dust_relay_fee), 294);
+        assert_eq!(wallet.dust_limit(&p2tr, dust_relay_fee), 330);
+        assert_eq!(wallet.dust_limit(&p2sh, dust_relay_fee), 540);
+        assert_eq!(
+            wallet.dust_limit(&p2wpkh, FeeRate::from_sat_per_vb(1.0)),
+            98
+        );
+    }
+
+    #[test]
+    fn estimate_funding_weight_matches_signed_transaction() {
+        let chain = MockChain::default();
+        chain.set_fee_rate(FeeRate::from_sat_per_vb(1.0));
+        let (bdk_wallet, _outpoints) = funded_signing_wallet(chain, &[100_000]);
+        let wallet = LightningWallet::new(bdk_wallet);
+        let output_script = Script::new_v0_wsh(&bdk::bitcoin::WScriptHash::hash(&[1]));
+
+        let estimated_weight = wallet.estimate_funding_weight(50_000, 6).unwrap();
+        let tx = wallet
+            .construct_funding_transaction(&output_script, 50_000, 6)
+            .unwrap();
+
+        assert_eq!(tx.input.len(), 1);
+        assert!(estimated_weight >= tx.get_weight());
+        assert!(estimated_weight - tx.get_weight() <= 4);
+        assert!((150..=160).contains(&((estimated_weight + 3) / 4)));
+    }
+
+    #[test]
+    fn funding_without_all_signatures_is_rejected() {
+        let chain = MockChain::default();
+        chain.set_fee_rate(FeeRate::from_sat_per_vb(1.0));
+        let (bdk_wallet, _outpoints) = funded_multisig_wallet(chain, &[100_000]);
+        let wallet = LightningWallet::new(bdk_wallet);
+        let output_script = Script::new_v0_wsh(&bdk::bitcoin::WScriptHash::hash(&[1]));
+
+        let (psbt, _tx_details) = wallet
+            .construct_funding_psbt(&output_script, 50_000, FeeRate::from_sat_per_vb(1.0))
+            .unwrap();
+        assert!(psbt
+            .inputs
+            .iter()
+            .all(|input| input.partial_sigs.len() == 1));
+
+        assert!(matches!(
+            wallet.construct_funding_transaction(&output_script, 50_000, 6),
+            Err(Error::IncompleteSignature)
+        ));
+        assert!(matches!(
+            wallet.finalize_signed_psbt(psbt),
+            Err(Error::IncompleteSignature)
+        ));
+    }
+
+    #[test]
+    fn funding_respects_min_confirmations() {
+        let chain = MockChain::default();
+        let (bdk_wallet, outpoints) = funded_signing_wallet_at_heights(
+            chain.clone(),
+            &[(90_000, None), (70_000, Some(10)), (60_000, Some(5))],
+        );
+        chain.set_height(10);
+        let wallet = LightningWallet::new(bdk_wallet);
+        let output_script = Script::new_v0_wsh(&bdk::bitcoin::WScriptHash::hash(&[1]));
+        let fee_rate = FeeRate::from_sat_per_vb(1.0);
+        let options = FundingOptions {
+            fee: FundingFee::FeeRate(fee_rate),
+            ..Default::default()
+        };
+
+        for _ in 0..5 {
+            let tx = wallet
+                .construct_funding_transaction_with_options(&output_script, 50_000, &options)
+                .unwrap();
+            assert!(tx
+                .input
+                .iter()
+                .all(|input| input.previous_output != outpoints[0]));
+        }
+
+        let options = FundingOptions {
+            min_confirmations: 3,
+            ..options
+        };
+        let tx = wallet
+            .construct_funding_transaction_with_options(&output_script, 50_000, &options)
+            .unwrap();
+        assert_eq!(tx.input.len(), 1);
+        assert_eq!(tx.input[0].previous_output, outpoints[2]);
+
+        let options = FundingOptions {
+            min_confirmations: 7,
+            ..options
+        };
+        assert!(wallet
+            .construct_funding_transaction_with_options(&output_script, 50_000, &options)
+            .is_err());
+    }
+
+    #[test]
+    fn on_confirmed_fires_once_per_newly_confirmed_tx() {
+        let chain = MockChain::default();
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+        let channel_manager = Arc::new(MockConfirm::default());
+        let chain_monitor = Arc::new(MockConfirm::default());
+
+        let confirmations = Arc::new(Mutex::new(vec![]));
+        let recorded = Arc::clone(&confirmations);
+        wallet.set_on_confirmed(Arc::new(move |txid, height| {
+            recorded.lock().unwrap().push((txid, height));
+        }));
+
+        chain.set_height(120);
+        let first_script = Script::new_v0_wpkh(&bdk::bitcoin::WPubkeyHash::hash(&[1]));
+        let first_tx = spending_tx(Default::default(), first_script.clone(), 1000);
+        chain.add_script_history(&first_script, confirmed_status(110), first_tx.clone());
+        chain.set_tx_status(first_tx.txid(), Some(confirmed_status(110)));
+        chain.set_position(first_tx.txid(), 0);
+        wallet.register_tx(&first_tx.txid(), &first_script);
+
+        wallet
+            .sync(channel_manager.clone(), chain_monitor.clone())
+            .unwrap();
+
+        let second_script = Script::new_v0_wpkh(&bdk::bitcoin::WPubkeyHash::hash(&[2]));
+        let second_tx = spending_tx(Default::default(), second_script.clone(), 2000);
+        chain.add_script_history(&second_script, confirmed_status(115), second_tx.clone());
+        chain.set_tx_status(second_tx.txid(), Some(confirmed_status(115)));
+        chain.set_position(second_tx.txid(), 0);
+        wallet.register_tx(&second_tx.txid(), &second_script);
+
+        for _ in 0..2 {
+            wallet
+                .sync(channel_manager.clone(), chain_monitor.clone())
+                .unwrap();
+        }
+
+        assert_eq!(
+            *confirmations.lock().unwrap(),
+            vec![(first_tx.txid(), 110), (second_tx.txid(), 115)]
+        );
+    }
+
+    #[test]
+    fn funding_psbt_spends_foreign_utxos_unsigned() {
+        let chain = MockChain::default();
+        chain.set_fee_rate(FeeRate::from_sat_per_vb(1.0));
+        let (bdk_wallet, outpoints) = funded_signing_wallet(chain, &[30_000]);
+        let wallet = LightningWallet::new(bdk_wallet);
+        let output_script = Script::new_v0_wsh(&bdk::bitcoin::WScriptHash::hash(&[1]));
+
+        let foreign_script = Script::new_v0_wpkh(&bdk::bitcoin::WPubkeyHash::hash(&[2]));
+        let foreign_tx = spending_tx(Default::default(), foreign_script, 40_000);
+        let foreign_outpoint = BitcoinOutPoint::new(foreign_tx.txid(), 0);
+        let foreign_input = psbt::Input {
+            witness_utxo: Some(foreign_tx.output[0].clone()),
+            non_witness_utxo: Some(foreign_tx),
+            ..Default::default()
+        };
+
+        let psbt = wallet
+            .construct_funding_psbt_with_foreign_utxos(
+                &output_script,
+                60_000,
+                6,
+                vec![(foreign_outpoint, foreign_input, P2WPKH_SATISFACTION_WEIGHT)],
+            )
+            .unwrap();
+
+        let inputs = &psbt.global.unsigned_tx.input;
+        assert_eq!(inputs.len(), 2);
+        for (txin, psbt_input) in inputs.iter().zip(psbt.inputs.iter()) {
+            let signed =
+                !psbt_input.partial_sigs.is_empty() || psbt_input.final_script_witness.is_some();
+            if txin.previous_output == foreign_outpoint {
+                assert!(!signed);
+            } else {
+                assert_eq!(txin.previous_output, outpoints[0]);
+                assert!(signed);
+            }
+        }
+        assert!(matches!(
+            wallet.finalize_signed_psbt(psbt),
+            Err(Error::IncompleteSignature)
+        ));
+    }
+
+    #[test]
+    fn best_block_matches_tip() {
+        let chain = MockChain::default();
+        chain.set_height(42);
+        let wallet = LightningWallet::new(test_wallet(chain));
+
+        let (tip_height, tip_header) = wallet.get_tip().unwrap();
+        let best_block = wallet.best_block().unwrap();
+
+        assert_eq!(best_block.height(), tip_height);
+        assert_eq!(best_block.block_hash(), tip_header.block_hash());
+    }
+
+    #[test]
+    fn get_balance_waits_for_onchain_sync() {
+        let chain = MockChain::default();
+        let wallet = Arc::new(LightningWallet::new(test_wallet(chain.clone())));
+
+        let sync_delay = Duration::from_millis(300);
+        chain.delay_wallet_sync(sync_delay);
+        let syncing_wallet = Arc::clone(&wallet);
+        let started_at = Instant::now();
+        let sync = std::thread::spawn(move || syncing_wallet.sync_onchain_wallet());
+        std::thread::sleep(Duration::from_millis(50));
+
+        assert_eq!(wallet.get_balance().unwrap(), 0);
+        assert!(started_at.elapsed() >= sync_delay);
+
+        sync.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn cached_fee_estimates_do_not_wait_for_onchain_sync() {
+        let chain = MockChain::default();
+        chain.set_fee_rate(FeeRate::from_sat_per_vb(2.0));
+        let wallet = Arc::new(LightningWallet::new(test_wallet(chain.clone())));
+        wallet.refresh_fee_cache().unwrap();
+
+        let sync_delay = Duration::from_millis(500);
+        chain.delay_wallet_sync(sync_delay);
+        let syncing_wallet = Arc::clone(&wallet);
+        let sync = std::thread::spawn(move || syncing_wallet.sync_onchain_wallet());
+        std::thread::sleep(Duration::from_millis(50));
+
+        let started_at = Instant::now();
+        let estimate = wallet.get_est_sat_per_1000_weight(ConfirmationTarget::Normal);
+        assert!(started_at.elapsed() < sync_delay / 2);
+        assert_eq!(estimate, 500);
+
+        sync.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn funding_feerate_estimate_respects_floor() {
+        let chain = MockChain::default();
+        chain.set_fee_rate(FeeRate::from_sat_per_vb(0.25));
+        let (bdk_wallet, _outpoints) = funded_signing_wallet(chain, &[100_000]);
+        let wallet = LightningWallet::new(bdk_wallet);
+        let output_script = Script::new_v0_wsh(&bdk::bitcoin::WScriptHash::hash(&[1]));
+
+        wallet
+            .construct_funding_transaction(&output_script, 50_000, 6)
+            .unwrap();
+        assert_eq!(
+            wallet.last_funding_feerate(),
+            Some(FeeRate::from_sat_per_vb(1.0))
+        );
+
+        wallet.set_min_funding_feerate(FeeRate::from_sat_per_vb(3.0));
+        wallet
+            .construct_funding_transaction(&output_script, 50_000, 6)
+            .unwrap();
+        assert_eq!(
+            wallet.last_funding_feerate(),
+            Some(FeeRate::from_sat_per_vb(3.0))
+        );
+
+        wallet
+            .construct_funding_transaction_with_feerate(
+                &output_script,
+                50_000,
+                FeeRate::from_sat_per_vb(2.0),
+            )
+            .unwrap();
+        assert_eq!(
+            wallet.last_funding_feerate(),
+            Some(FeeRate::from_sat_per_vb(2.0))
+        );
+    }
+
+    #[test]
+    fn test_mempool_accept_uses_backend_hook() {
+        let wallet = LightningWallet::new(test_wallet(MockChain::default()));
+        let accepted = spending_tx(Default::default(), Script::new(), 1000);
+        let rejected = spending_tx(Default::default(), Script::new(), 1);
+
+        assert!(matches!(
+            wallet.test_mempool_accept(&accepted),
+            Err(Error::Unsupported)
+        ));
+
+        let accepted_txid = accepted.txid();
+        wallet.set_mempool_accept(Box::new(move |tx| Ok(tx.txid() == accepted_txid)));
+
+        assert!(wallet.test_mempool_accept(&accepted).unwrap());
+        assert!(!wallet.test_mempool_accept(&rejected).unwrap());
+    }
+
+    #[test]
+    fn regtest_wallet_rejects_other_networks() {
+        let wallet = LightningWallet::new(regtest_wallet(MockChain::default()));
+        let fee_rate = FeeRate::from_sat_per_vb(1.0);
+        let script_pubkey = Script::new_v0_wpkh(&bdk::bitcoin::WPubkeyHash::hash(&[1]));
+
+        for network in [Network::Testnet, Network::Bitcoin] {
+            let address = Address::from_script(&script_pubkey, network).unwrap();
+            assert!(matches!(
+                wallet.send_to_address(&address, 10_000, fee_rate),
+                Err(Error::NetworkMismatch {
+                    expected: Network::Regtest,
+                    got,
+                }) if got == network
+            ));
+
+            let options = FundingOptions {
+                change_policy: ChangePolicy::DrainTo(address),
+                ..Default::default()
+            };
+            assert!(matches!(
+                wallet.construct_funding_transaction_with_options(&script_pubkey, 10_000, &options),
+                Err(Error::NetworkMismatch { .. })
+            ));
+        }
+
+        let regtest_address = Address::from_script(&script_pubkey, Network::Regtest).unwrap();
+        assert!(matches!(
+            wallet.send_to_address(&regtest_address, 10_000, fee_rate),
+            Err(Error::InsufficientFunds { .. })
+        ));
+
+        let legacy_address = Address::from_script(
+            &Script::new_p2pkh(&bdk::bitcoin::PubkeyHash::hash(&[2])),
+            Network::Testnet,
+        )
+        .unwrap();
+        assert!(matches!(
+            wallet.send_to_address(&legacy_address, 10_000, fee_rate),
+            Err(Error::InsufficientFunds { .. })
+        ));
+    }
+
+    #[test]
+    fn health_summarizes_wallet_state() {
+        let chain = MockChain::default();
+        let (bdk_wallet, _outpoints) =
+            funded_signing_wallet_at_heights(chain.clone(), &[(60_000, Some(5)), (40_000, None)]);
+        chain.set_height(10);
+        let wallet = LightningWallet::new(bdk_wallet);
+        let channel_manager = Arc::new(MockConfirm::default());
+        let chain_monitor = Arc::new(MockConfirm::default());
+
+        let script_pubkey = Script::new_v0_wpkh(&bdk::bitcoin::WPubkeyHash::hash(&[1]));
+        wallet.register_tx(&Txid::hash(&[1]), &script_pubkey);
+        wallet.register_output(WatchedOutput {
+            block_hash: None,
+            outpoint: OutPoint {
+                txid: Txid::hash(&[2]),
+                index: 0,
+            },
+            script_pubkey,
+        });
+
+        wallet.set_retry_config(RetryConfig {
+            max_attempts: 2,
+            base_delay: Duration::ZERO,
+        });
+        chain.fail_next_height(1);
+        let health = wallet.health().unwrap();
+        assert_eq!(health.tip_height, 10);
+        assert_eq!(health.confirmed_balance, 60_000);
+        assert_eq!(health.last_sync_age, None);
+        assert_eq!(health.watched_count, 2);
+
+        wallet.sync(channel_manager, chain_monitor).unwrap();
+        chain.set_height(12);
+
+        let health = wallet.health().unwrap();
+        assert_eq!(health.tip_height, 10);
+        assert!(health.last_sync_age.is_some());
+    }
+
+    #[test]
+    fn addresses_come_from_the_requested_keychain() {
+        let chain = MockChain::default();
+        let expected = change_wallet(chain.clone());
+        let wallet = LightningWallet::new(change_wallet(chain));
+
+        let external = wallet.get_unused_address().unwrap();
+        let internal = wallet
+            .get_unused_address_from(KeychainKind::Internal)
+            .unwrap();
+        let peeked = wallet
+            .get_keychain_address(AddressIndex::Peek(3), KeychainKind::Internal)
+            .unwrap();
+
+        assert_eq!(
+            external,
+            expected.get_address(AddressIndex::Peek(0)).unwrap().address
+        );
+        assert_eq!(
+            internal,
+            expected
+                .get_internal_address(AddressIndex::Peek(0))
+                .unwrap()
+                .address
+        );
+        assert_ne!(external, internal);
+        assert_eq!(
+            peeked.address,
+            expected
+                .get_internal_address(AddressIndex::Peek(3))
+                .unwrap()
+                .address
+        );
+    }
+
+    #[test]
+    fn sweep_pays_to_the_requested_keychain() {
+        let chain = MockChain::default();
+        let expected = change_wallet(chain.clone());
+        let wallet = LightningWallet::new(change_wallet(chain));
+        let script_pubkey = wallet
+            .get_address(AddressIndex::Peek(0))
+            .unwrap()
+            .address
+            .script_pubkey();
+        let descriptors = vec![static_output(0, 50_000, &script_pubkey)];
+
+        let tx = wallet
+            .create_sweep_transaction_to_keychain(
+                &descriptors,
+                FeeRate::from_sat_per_vb(1.0),
+                0,
+                KeychainKind::Internal,
+            )
+            .unwrap();
+
+        let change_script = expected
+            .get_internal_address(AddressIndex::Peek(0))
+            .unwrap()
+            .address
+            .script_pubkey();
+        assert_eq!(tx.output.len(), 1);
+        assert_eq!(tx.output[0].script_pubkey, change_script);
+    }
+
+    #[test]
+    fn bump_fee_of_transaction_confirmed_since_sync_fails() {
+        let chain = MockChain::default();
+        let (bdk_wallet, outpoints) =
+            funded_signing_wallet_at_heights(chain.clone(), &[(50_000, None)]);
+        let wallet = LightningWallet::new(bdk_wallet);
+        let txid = outpoints[0].txid;
+        assert!(!wallet.is_confirmed(&txid).unwrap());
+
+        // confirms after the user decided to bump it but before the wallet syncs
+        chain.set_tx_status(txid, Some(confirmed_status(2)));
+
+        assert!(wallet.is_confirmed(&txid).unwrap());
+        assert!(matches!(
+            wallet.bump_fee(&txid, FeeRate::from_sat_per_vb(20.0)),
+            Err(Error::AlreadyConfirmed)
+        ));
+        assert_eq!(wallet.rebroadcast_unconfirmed().unwrap(), 0);
+        assert!(chain.broadcasts().is_empty());
+    }
+
+    #[test]
+    fn confirmed_blocks_are_visited_in_height_and_position_order() {
+        let chain = MockChain::default();
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+
+        let script_pubkey = Script::new_v0_wpkh(&bdk::bitcoin::WPubkeyHash::hash(&[1]));
+        let txs = [(200, 5), (100, 0), (200, 2)]
+            .iter()
+            .map(|(height, position)| {
+                let value = (*height + *position) as u64;
+                let tx = spending_tx(Default::default(), script_pubkey.clone(), value);
+                chain.add_script_history(&script_pubkey, confirmed_status(*height), tx.clone());
+                chain.set_position(tx.txid(), *position as usize);
+                wallet.register_tx(&tx.txid(), &script_pubkey);
+                tx
+            })
+            .collect::<Vec<Transaction>>();
+        chain.set_height(200);
+
+        let mut blocks = vec![];
+        wallet
+            .for_each_confirmed_block(|height, block_header, tx_list| {
+                assert_eq!(*block_header, header(height));
+                let txids = tx_list
+                    .iter()
+                    .map(|(position, tx)| (*position, tx.txid()))
+                    .collect::<Vec<_>>();
+                blocks.push((height, txids));
+            })
+            .unwrap();
+
+        assert_eq!(
+            blocks,
+            vec![
+                (100, vec![(0, txs[1].txid())]),
+                (200, vec![(2, txs[2].txid()), (5, txs[0].txid())]),
+            ]
+        );
+    }
+
+    #[test]
+    fn sync_refetches_positions_after_a_reorg_at_the_same_height() {
+        let chain = MockChain::default();
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+        let channel_manager = Arc::new(MockConfirm::default());
+        let chain_monitor = Arc::new(MockConfirm::default());
+
+        let script_pubkey = Script::new_v0_wpkh(&bdk::bitcoin::WPubkeyHash::hash(&[1]));
+        let tx = spending_tx(Default::default(), script_pubkey.clone(), 1000);
+        chain.set_height(120);
+        chain.add_script_history(&script_pubkey, confirmed_status(110), tx.clone());
+        chain.set_tx_status(tx.txid(), Some(confirmed_status(110)));
+        chain.set_position(tx.txid(), 2);
+        wallet.register_tx(&tx.txid(), &script_pubkey);
+        wallet
+            .sync(channel_manager.clone(), chain_monitor.clone())
+            .unwrap();
+
+        let reorged_header = BlockHeader {
+            nonce: 1,
+            ..header(110)
+        };
+        chain.set_header(110, reorged_header);
+        chain.set_position(tx.txid(), 5);
+        wallet.sync(channel_manager.clone(), chain_monitor).unwrap();
+
+        assert_eq!(chain.position_calls(), 2);
+        assert!(channel_manager.headers().contains(&reorged_header));
+    }
+
+    #[test]
+    fn min_confirmations_are_counted_from_the_last_synced_tip() {
+        let chain = MockChain::default();
+        let (bdk_wallet, outpoints) = funded_signing_wallet_at_heights(
+            chain.clone(),
+            &[(70_000, Some(10)), (60_000, Some(5))],
+        );
+        let wallet = LightningWallet::new(bdk_wallet);
+        chain.set_height(20);
+        wallet
+            .sync(
+                Arc::new(MockConfirm::default()),
+                Arc::new(MockConfirm::default()),
+            )
+            .unwrap();
+        // the backend is no longer asked for the tip when funding
+        chain.set_height(10);
+
+        let output_script = Script::new_v0_wsh(&bdk::bitcoin::WScriptHash::hash(&[1]));
+        let options = FundingOptions {
+            fee: FundingFee::FeeRate(FeeRate::from_sat_per_vb(1.0)),
+            utxos: vec![outpoints[0]],
+            min_confirmations: 11,
+            ..Default::default()
+        };
+        let tx = wallet
+            .construct_funding_transaction_with_options(&output_script, 50_000, &options)
+            .unwrap();
+        assert_eq!(tx.input[0].previous_output, outpoints[0]);
+    }
+
+    #[test]
+    fn funding_rejects_manually_selected_shallow_utxos() {
+        let chain = MockChain::default();
+        let (bdk_wallet, outpoints) =
+            funded_signing_wallet_at_heights(chain, &[(90_000, None), (70_000, Some(10))]);
+        let wallet = LightningWallet::new(bdk_wallet);
+        let output_script = Script::new_v0_wsh(&bdk::bitcoin::WScriptHash::hash(&[1]));
+        let options = FundingOptions {
+            fee: FundingFee::FeeRate(FeeRate::from_sat_per_vb(1.0)),
+            utxos: vec![outpoints[0]],
+            ..Default::default()
+        };
+
+        match wallet.construct_funding_transaction_with_options(&output_script, 50_000, &options) {
+            Err(Error::ShallowUtxo {
+                outpoint,
+                min_confirmations: 1,
+            }) => assert_eq!(outpoint, outpoints[0]),
+            result => panic!("expected a shallow utxo error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn signet_wallet_accepts_testnet_addresses() {
+        let wallet = LightningWallet::new(signet_wallet(MockChain::default()));
+        let fee_rate = FeeRate::from_sat_per_vb(1.0);
+        let scripts = [
+            Script::new_v0_wpkh(&bdk::bitcoin::WPubkeyHash::hash(&[1])),
+            Script::new_p2pkh(&bdk::bitcoin::PubkeyHash::hash(&[2])),
+        ];
+
+        for script_pubkey in &scripts {
+            let address = Address::from_script(script_pubkey, Network::Testnet).unwrap();
+            assert!(matches!(
+                wallet.send_to_address(&address, 10_000, fee_rate),
+                Err(Error::InsufficientFunds { .. })
+            ));
+        }
+
+        let mainnet_address = Address::from_script(&scripts[0], Network::Bitcoin).unwrap();
+        assert!(matches!(
+            wallet.send_to_address(&mainnet_address, 10_000, fee_rate),
+            Err(Error::NetworkMismatch {
+                expected: Network::Signet,
+                got: Network::Bitcoin,
+            })
+        ));
+    }
+
+    #[cfg(feature = "esplora")]
+    #[test]
+    fn esplora_not_found_errors_are_not_retried() {
+        let esplora = |e| bdk::Error::Esplora(Box::new(e));
+
+        assert!(is_transient(&esplora(EsploraError::HttpResponse(503))));
+        assert!(is_transient(&esplora(EsploraError::HttpResponse(429))));
+        assert!(!is_transient(&esplora(EsploraError::HttpResponse(404))));
+        assert!(!is_transient(&esplora(EsploraError::TransactionNotFound(
+            Txid::hash(&[1])
+        ))));
+        assert!(!is_transient(&esplora(EsploraError::HeaderHeightNotFound(
+            100
+        ))));
+        assert!(!is_transient(&esplora(EsploraError::NoHeader)));
+    }
+
+    #[test]
+    fn best_effort_sync_counts_the_tip_update_as_progress() {
+        let chain = MockChain::default();
+        let wallet = LightningWallet::new(test_wallet(chain.clone()));
+        let channel_manager = Arc::new(MockConfirm::default());
+        let chain_monitor = Arc::new(MockConfirm::default());
+        chain.set_height(120);
+        chain.fail_wallet_sync();
+
+        let (_stats, errors) = wallet
+            .sync_best_effort(channel_manager.clone(), chain_monitor)
+            .unwrap();
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(channel_manager.best_blocks(), vec![120]);
     }
 }
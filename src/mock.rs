@@ -0,0 +1,572 @@
+use bdk::bitcoin::hashes::Hash;
+use bdk::bitcoin::{BlockHeader, Network, OutPoint, Script, Transaction, TxIn, TxOut, Txid};
+use bdk::blockchain::{Blockchain, Capability, IndexedChain, Progress, TxStatus};
+use bdk::database::{BatchDatabase, BatchOperations, MemoryDatabase};
+use bdk::electrum_client;
+use bdk::wallet::{AddressIndex, Wallet};
+use bdk::{ConfirmationTime, Error, FeeRate, KeychainKind, LocalUtxo, TransactionDetails};
+use lightning::chain::transaction::TransactionData;
+use lightning::chain::Confirm;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const TEST_DESCRIPTOR: &str = "wpkh([c258d2e4/84h/1h/0h]tpubDDYkZojQFQjht8Tm4jsS3iuEmKjTiEGjG6KnuFNKKJb5A6ZUCUZKdvLdSDWofKi4ToRCwb9poe1XdqfUnP4jaJjCB2Zwv11ZLgSbnZSNecE/0/*)";
+
+/// the account key of TEST_DESCRIPTOR
+const TEST_XPUB: &str = "tpubDDYkZojQFQjht8Tm4jsS3iuEmKjTiEGjG6KnuFNKKJb5A6ZUCUZKdvLdSDWofKi4ToRCwb9poe1XdqfUnP4jaJjCB2Zwv11ZLgSbnZSNecE";
+
+/// the master key from bip32 test vector 1
+pub const TEST_XPRV: &str = "tprv8ZgxMBicQKsPeDgjzdC36fs6bMjGApWDNLR9erAXMs5skhMv36j9MV5ecvfavji5khqjWaWSFhN3YcCUUdiKH6isR4Pwy3U5y5egddBr16m";
+
+#[derive(Default)]
+struct MockState {
+    fee_rate: Option<FeeRate>,
+    target_fee_rates: HashMap<usize, FeeRate>,
+    estimate_fee_calls: usize,
+    script_histories: HashMap<Script, Vec<(TxStatus, Transaction)>>,
+    script_history_calls: usize,
+    positions: HashMap<Txid, usize>,
+    broadcasts: Vec<Txid>,
+    fail_broadcasts: bool,
+    height: u32,
+    tx_statuses: HashMap<Txid, TxStatus>,
+    headers: HashMap<u32, BlockHeader>,
+    tx_status_calls: usize,
+    failing_scripts: HashSet<Script>,
+    tx_status_failures: usize,
+    permanent_tx_status_failures: bool,
+    queued_heights: Vec<u32>,
+    height_failures: usize,
+    position_calls: usize,
+    fail_wallet_sync: bool,
+    wallet_sync_delay: Option<Duration>,
+}
+
+/// an in-memory blockchain backend with programmable responses
+/// that records how often it was queried
+#[derive(Clone, Default)]
+pub struct MockChain {
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockChain {
+    /// sets the feerate returned by estimate_fee, which fails until set
+    pub fn set_fee_rate(&self, fee_rate: FeeRate) {
+        self.state.lock().unwrap().fee_rate = Some(fee_rate);
+    }
+
+    /// sets the feerate returned by estimate_fee for one block target,
+    /// overriding set_fee_rate for that target
+    pub fn set_fee_rate_for_target(&self, target: usize, fee_rate: FeeRate) {
+        let mut state = self.state.lock().unwrap();
+        state.target_fee_rates.insert(target, fee_rate);
+    }
+
+    pub fn estimate_fee_calls(&self) -> usize {
+        self.state.lock().unwrap().estimate_fee_calls
+    }
+
+    /// adds a transaction to the history of the given script
+    pub fn add_script_history(&self, script: &Script, status: TxStatus, tx: Transaction) {
+        let mut state = self.state.lock().unwrap();
+        state
+            .script_histories
+            .entry(script.clone())
+            .or_default()
+            .push((status, tx));
+    }
+
+    pub fn script_history_calls(&self) -> usize {
+        self.state.lock().unwrap().script_history_calls
+    }
+
+    /// makes every subsequent history request for the script fail
+    pub fn fail_script_history(&self, script: &Script) {
+        self.state
+            .lock()
+            .unwrap()
+            .failing_scripts
+            .insert(script.clone());
+    }
+
+    /// makes history requests for the script succeed again
+    pub fn restore_script_history(&self, script: &Script) {
+        self.state.lock().unwrap().failing_scripts.remove(script);
+    }
+
+    /// removes all transactions from the history of the given script
+    pub fn clear_script_history(&self, script: &Script) {
+        self.state.lock().unwrap().script_histories.remove(script);
+    }
+
+    /// sets the current tip height
+    pub fn set_height(&self, height: u32) {
+        self.state.lock().unwrap().height = height;
+    }
+
+    /// heights returned by get_height, in order, before falling back to
+    /// the height set with set_height. simulates the tip moving
+    pub fn queue_heights(&self, heights: Vec<u32>) {
+        self.state.lock().unwrap().queued_heights = heights;
+    }
+
+    /// makes the next count get_height requests fail with a connection error
+    pub fn fail_next_height(&self, count: usize) {
+        self.state.lock().unwrap().height_failures = count;
+    }
+
+    /// overrides the header returned for the block at the given height
+    pub fn set_header(&self, height: u32, header: BlockHeader) {
+        self.state.lock().unwrap().headers.insert(height, header);
+    }
+
+    /// sets the status returned for a transaction, None means unknown
+    pub fn set_tx_status(&self, txid: Txid, status: Option<TxStatus>) {
+        let mut state = self.state.lock().unwrap();
+        match status {
+            Some(status) => state.tx_statuses.insert(txid, status),
+            None => state.tx_statuses.remove(&txid),
+        };
+    }
+
+    /// makes the next count tx status requests fail with a connection error
+    pub fn fail_next_tx_status(&self, count: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.tx_status_failures = count;
+        state.permanent_tx_status_failures = false;
+    }
+
+    /// makes the next count tx status requests fail with an error
+    /// retrying won't fix
+    pub fn fail_next_tx_status_permanently(&self, count: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.tx_status_failures = count;
+        state.permanent_tx_status_failures = true;
+    }
+
+    pub fn tx_status_calls(&self) -> usize {
+        self.state.lock().unwrap().tx_status_calls
+    }
+
+    /// txids of all transactions successfully broadcast, in order
+    pub fn broadcasts(&self) -> Vec<Txid> {
+        self.state.lock().unwrap().broadcasts.clone()
+    }
+
+    /// makes every subsequent broadcast fail
+    pub fn fail_broadcasts(&self) {
+        self.state.lock().unwrap().fail_broadcasts = true;
+    }
+
+    /// makes every subsequent onchain wallet sync fail
+    pub fn fail_wallet_sync(&self) {
+        self.state.lock().unwrap().fail_wallet_sync = true;
+    }
+
+    /// makes every subsequent onchain wallet sync take at least this long,
+    /// like a sync against a slow server would
+    pub fn delay_wallet_sync(&self, delay: Duration) {
+        self.state.lock().unwrap().wallet_sync_delay = Some(delay);
+    }
+
+    pub fn position_calls(&self) -> usize {
+        self.state.lock().unwrap().position_calls
+    }
+
+    /// sets the position of a transaction within its block
+    pub fn set_position(&self, txid: Txid, position: usize) {
+        self.state.lock().unwrap().positions.insert(txid, position);
+    }
+}
+
+impl Blockchain for MockChain {
+    fn get_capabilities(&self) -> HashSet<Capability> {
+        HashSet::new()
+    }
+
+    fn setup<D: BatchDatabase, P: 'static + Progress>(
+        &self,
+        _stop_gap: Option<usize>,
+        _database: &mut D,
+        _progress_update: P,
+    ) -> Result<(), Error> {
+        let state = self.state.lock().unwrap();
+        if state.fail_wallet_sync {
+            return Err(Error::Generic("wallet sync failed".to_string()));
+        }
+        let delay = state.wallet_sync_delay;
+        drop(state);
+
+        if let Some(delay) = delay {
+            std::thread::sleep(delay);
+        }
+        Ok(())
+    }
+
+    fn get_tx(&self, _txid: &Txid) -> Result<Option<Transaction>, Error> {
+        Ok(None)
+    }
+
+    fn broadcast(&self, tx: &Transaction) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        if state.fail_broadcasts {
+            return Err(Error::Generic("broadcast rejected".to_string()));
+        }
+        state.broadcasts.push(tx.txid());
+        Ok(())
+    }
+
+    fn get_height(&self) -> Result<u32, Error> {
+        let mut state = self.state.lock().unwrap();
+        if state.height_failures > 0 {
+            state.height_failures -= 1;
+            return Err(connection_error());
+        }
+        if state.queued_heights.is_empty() {
+            return Ok(state.height);
+        }
+        Ok(state.queued_heights.remove(0))
+    }
+
+    fn estimate_fee(&self, target: usize) -> Result<FeeRate, Error> {
+        let mut state = self.state.lock().unwrap();
+        state.estimate_fee_calls += 1;
+        state
+            .target_fee_rates
+            .get(&target)
+            .copied()
+            .or(state.fee_rate)
+            .ok_or_else(|| Error::Generic("no fee estimate available".to_string()))
+    }
+}
+
+impl IndexedChain for MockChain {
+    fn get_header(&self, height: u32) -> Result<BlockHeader, Error> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .headers
+            .get(&height)
+            .cloned()
+            .unwrap_or_else(|| header(height)))
+    }
+
+    fn get_tx_status(&self, txid: &Txid) -> Result<Option<TxStatus>, Error> {
+        let mut state = self.state.lock().unwrap();
+        state.tx_status_calls += 1;
+        if state.tx_status_failures > 0 {
+            state.tx_status_failures -= 1;
+            if state.permanent_tx_status_failures {
+                return Err(Error::Generic("tx status unavailable".to_string()));
+            }
+            return Err(connection_error());
+        }
+        Ok(state.tx_statuses.get(txid).cloned())
+    }
+
+    fn get_script_tx_history(
+        &self,
+        script: &Script,
+    ) -> Result<Vec<(TxStatus, Transaction)>, Error> {
+        let mut state = self.state.lock().unwrap();
+        state.script_history_calls += 1;
+        if state.failing_scripts.contains(script) {
+            return Err(connection_error());
+        }
+        Ok(state
+            .script_histories
+            .get(script)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    fn get_position_in_block(&self, txid: &Txid, _height: usize) -> Result<Option<usize>, Error> {
+        let mut state = self.state.lock().unwrap();
+        state.position_calls += 1;
+        Ok(state.positions.get(txid).copied())
+    }
+}
+
+/// the error an electrum backend returns when it can't be reached
+fn connection_error() -> Error {
+    Error::Electrum(electrum_client::Error::IOError(io::Error::new(
+        io::ErrorKind::ConnectionReset,
+        "backend unreachable",
+    )))
+}
+
+/// a watch-only testnet wallet backed by the given mock chain
+pub fn test_wallet(chain: MockChain) -> Wallet<MockChain, MemoryDatabase> {
+    funded_wallet(chain, &[]).0
+}
+
+/// a testnet wallet that can sign, backed by the given mock chain
+pub fn signing_wallet(chain: MockChain) -> Wallet<MockChain, MemoryDatabase> {
+    funded_signing_wallet(chain, &[]).0
+}
+
+/// a regtest wallet that can sign, backed by the given mock chain
+pub fn regtest_wallet(chain: MockChain) -> Wallet<MockChain, MemoryDatabase> {
+    network_wallet(chain, Network::Regtest)
+}
+
+/// a signet wallet that can sign, backed by the given mock chain
+pub fn signet_wallet(chain: MockChain) -> Wallet<MockChain, MemoryDatabase> {
+    network_wallet(chain, Network::Signet)
+}
+
+fn network_wallet(chain: MockChain, network: Network) -> Wallet<MockChain, MemoryDatabase> {
+    let descriptor = format!("wpkh({}/84'/1'/0'/0/*)", TEST_XPRV);
+    Wallet::new(&descriptor, None, network, MemoryDatabase::default(), chain).unwrap()
+}
+
+/// a testnet wallet that can sign with separate external and internal
+/// (change) keychains, backed by the given mock chain. the first external
+/// script is known to the wallet so outputs paying to it can be signed for
+pub fn change_wallet(chain: MockChain) -> Wallet<MockChain, MemoryDatabase> {
+    let descriptor = format!("wpkh({}/84'/1'/0'/0/*)", TEST_XPRV);
+    let change_descriptor = format!("wpkh({}/84'/1'/0'/1/*)", TEST_XPRV);
+    let mut database = MemoryDatabase::default();
+
+    let addresses = Wallet::new(
+        &descriptor,
+        None,
+        Network::Testnet,
+        MemoryDatabase::default(),
+        chain.clone(),
+    )
+    .unwrap();
+    let script_pubkey = addresses
+        .get_address(AddressIndex::Peek(0))
+        .unwrap()
+        .script_pubkey();
+    database
+        .set_script_pubkey(&script_pubkey, KeychainKind::External, 0)
+        .unwrap();
+
+    Wallet::new(
+        &descriptor,
+        Some(&change_descriptor),
+        Network::Testnet,
+        database,
+        chain,
+    )
+    .unwrap()
+}
+
+/// a watch-only testnet wallet backed by the given mock chain holding
+/// one confirmed utxo for each of the given values
+pub fn funded_wallet(
+    chain: MockChain,
+    values: &[u64],
+) -> (Wallet<MockChain, MemoryDatabase>, Vec<OutPoint>) {
+    funded_wallet_with_descriptor(TEST_DESCRIPTOR, chain, &confirmed(values))
+}
+
+/// same as funded_wallet but the wallet can sign
+pub fn funded_signing_wallet(
+    chain: MockChain,
+    values: &[u64],
+) -> (Wallet<MockChain, MemoryDatabase>, Vec<OutPoint>) {
+    funded_signing_wallet_at_heights(chain, &confirmed(values))
+}
+
+/// same as funded_signing_wallet but each utxo confirms at the given
+/// height, or is unconfirmed when it is None
+pub fn funded_signing_wallet_at_heights(
+    chain: MockChain,
+    utxos: &[(u64, Option<u32>)],
+) -> (Wallet<MockChain, MemoryDatabase>, Vec<OutPoint>) {
+    let descriptor = format!("wpkh({}/84'/1'/0'/0/*)", TEST_XPRV);
+    funded_wallet_with_descriptor(&descriptor, chain, utxos)
+}
+
+/// same as funded_wallet but the wallet is one of the two signers of a
+/// 2-of-2 multisig, so it can only partially sign
+pub fn funded_multisig_wallet(
+    chain: MockChain,
+    values: &[u64],
+) -> (Wallet<MockChain, MemoryDatabase>, Vec<OutPoint>) {
+    let descriptor = format!("wsh(multi(2,{}/0/*,{}/0/*))", TEST_XPRV, TEST_XPUB);
+    funded_wallet_with_descriptor(&descriptor, chain, &confirmed(values))
+}
+
+/// utxos for each of the values confirmed in block 1
+fn confirmed(values: &[u64]) -> Vec<(u64, Option<u32>)> {
+    values.iter().map(|value| (*value, Some(1))).collect()
+}
+
+fn funded_wallet_with_descriptor(
+    descriptor: &str,
+    chain: MockChain,
+    utxos: &[(u64, Option<u32>)],
+) -> (Wallet<MockChain, MemoryDatabase>, Vec<OutPoint>) {
+    let mut database = MemoryDatabase::default();
+    let mut outpoints = vec![];
+
+    // so the utxos have at least one confirmation
+    let max_height = utxos.iter().filter_map(|(_value, height)| *height).max();
+    if let Some(max_height) = max_height {
+        let mut state = chain.state.lock().unwrap();
+        state.height = state.height.max(max_height);
+    }
+
+    let addresses = Wallet::new(
+        descriptor,
+        None,
+        Network::Testnet,
+        MemoryDatabase::default(),
+        chain.clone(),
+    )
+    .unwrap();
+
+    for (index, (value, height)) in utxos.iter().enumerate() {
+        let script_pubkey = addresses
+            .get_address(AddressIndex::Peek(index as u32))
+            .unwrap()
+            .script_pubkey();
+        let funding_outpoint = OutPoint::new(Txid::hash(&index.to_be_bytes()), 0);
+        let tx = spending_tx(funding_outpoint, script_pubkey.clone(), *value);
+        let outpoint = OutPoint::new(tx.txid(), 0);
+
+        database
+            .set_script_pubkey(&script_pubkey, KeychainKind::External, index as u32)
+            .unwrap();
+        database
+            .set_utxo(&LocalUtxo {
+                outpoint,
+                txout: tx.output[0].clone(),
+                keychain: KeychainKind::External,
+            })
+            .unwrap();
+        database.set_raw_tx(&tx).unwrap();
+        database
+            .set_tx(&TransactionDetails {
+                transaction: Some(tx.clone()),
+                txid: tx.txid(),
+                received: *value,
+                sent: 0,
+                fee: Some(0),
+                confirmation_time: height.map(|height| ConfirmationTime {
+                    height,
+                    timestamp: 0,
+                }),
+                verified: true,
+            })
+            .unwrap();
+
+        outpoints.push(outpoint);
+    }
+
+    let wallet = Wallet::new(descriptor, None, Network::Testnet, database, chain).unwrap();
+    (wallet, outpoints)
+}
+
+/// records every call ldk's Confirm interface receives
+#[derive(Default)]
+pub struct MockConfirm {
+    relevant_txids: Mutex<Vec<Txid>>,
+    confirmed: Mutex<Vec<(u32, Vec<Txid>)>>,
+    unconfirmed: Mutex<Vec<Txid>>,
+    best_blocks: Mutex<Vec<u32>>,
+    headers: Mutex<Vec<BlockHeader>>,
+}
+
+impl MockConfirm {
+    pub fn set_relevant_txids(&self, txids: Vec<Txid>) {
+        *self.relevant_txids.lock().unwrap() = txids;
+    }
+
+    /// heights and txids passed to transactions_confirmed, in call order
+    pub fn confirmed(&self) -> Vec<(u32, Vec<Txid>)> {
+        self.confirmed.lock().unwrap().clone()
+    }
+
+    /// txids passed to transaction_unconfirmed, in call order
+    pub fn unconfirmed(&self) -> Vec<Txid> {
+        self.unconfirmed.lock().unwrap().clone()
+    }
+
+    /// heights passed to best_block_updated, in call order
+    pub fn best_blocks(&self) -> Vec<u32> {
+        self.best_blocks.lock().unwrap().clone()
+    }
+
+    /// headers passed to transactions_confirmed and best_block_updated,
+    /// in call order
+    pub fn headers(&self) -> Vec<BlockHeader> {
+        self.headers.lock().unwrap().clone()
+    }
+}
+
+impl Confirm for MockConfirm {
+    fn transactions_confirmed(&self, header: &BlockHeader, txdata: &TransactionData, height: u32) {
+        let txids = txdata.iter().map(|(_pos, tx)| tx.txid()).collect();
+        self.confirmed.lock().unwrap().push((height, txids));
+        self.headers.lock().unwrap().push(*header);
+    }
+
+    fn transaction_unconfirmed(&self, txid: &Txid) {
+        self.unconfirmed.lock().unwrap().push(*txid);
+    }
+
+    fn best_block_updated(&self, header: &BlockHeader, height: u32) {
+        self.best_blocks.lock().unwrap().push(height);
+        self.headers.lock().unwrap().push(*header);
+    }
+
+    fn get_relevant_txids(&self) -> Vec<Txid> {
+        self.relevant_txids.lock().unwrap().clone()
+    }
+}
+
+/// a deterministic header for the block at the given height
+pub fn header(height: u32) -> BlockHeader {
+    BlockHeader {
+        version: 1,
+        prev_blockhash: Default::default(),
+        merkle_root: Default::default(),
+        time: height,
+        bits: 0,
+        nonce: 0,
+    }
+}
+
+/// a status for a transaction confirmed at the given height
+pub fn confirmed_status(height: u32) -> TxStatus {
+    TxStatus {
+        confirmed: true,
+        block_height: Some(height),
+        block_hash: None,
+        block_time: None,
+    }
+}
+
+/// a transaction spending the given outpoint to the given script
+pub fn spending_tx(outpoint: OutPoint, script_pubkey: Script, value: u64) -> Transaction {
+    Transaction {
+        version: 2,
+        lock_time: 0,
+        input: vec![TxIn {
+            previous_output: outpoint,
+            script_sig: Script::new(),
+            sequence: 0xFFFFFFFF,
+            witness: vec![],
+        }],
+        output: vec![TxOut {
+            value,
+            script_pubkey,
+        }],
+    }
+}
+
+/// a status for a transaction that has not confirmed
+pub fn unconfirmed_status() -> TxStatus {
+    TxStatus {
+        confirmed: false,
+        block_height: None,
+        block_hash: None,
+        block_time: None,
+    }
+}
@@ -0,0 +1,163 @@
+//! end to end tests against a regtest bitcoind and electrs
+//! run with `cargo test --features integration-tests`
+#![cfg(feature = "integration-tests")]
+
+use bdk::bitcoin::blockdata::opcodes::all::OP_CHECKMULTISIG;
+use bdk::bitcoin::blockdata::script::Builder;
+use bdk::bitcoin::secp256k1::{Secp256k1, SecretKey};
+use bdk::bitcoin::{Amount, BlockHeader, Network, PrivateKey, Script, Txid};
+use bdk::blockchain::ElectrumBlockchain;
+use bdk::database::MemoryDatabase;
+use bdk::electrum_client::Client;
+use bdk::wallet::Wallet;
+use bdk::FeeRate;
+use bdk_ldk::LightningWallet;
+use electrsd::bitcoind::bitcoincore_rpc::RpcApi;
+use electrsd::bitcoind::{self, BitcoinD};
+use electrsd::ElectrsD;
+use lightning::chain::chaininterface::BroadcasterInterface;
+use lightning::chain::transaction::TransactionData;
+use lightning::chain::{Confirm, Filter};
+use std::sync::{Arc, Mutex};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// the master key from bip32 test vector 1
+const TEST_XPRV: &str = "tprv8ZgxMBicQKsPeDgjzdC36fs6bMjGApWDNLR9erAXMs5skhMv36j9MV5ecvfavji5khqjWaWSFhN3YcCUUdiKH6isR4Pwy3U5y5egddBr16m";
+
+type TestWallet = LightningWallet<ElectrumBlockchain, MemoryDatabase>;
+
+/// records the transactions ldk would be told confirmed
+#[derive(Default)]
+struct RecordingConfirm {
+    confirmed: Mutex<Vec<(u32, Txid)>>,
+    best_block: Mutex<Option<u32>>,
+}
+
+impl Confirm for RecordingConfirm {
+    fn transactions_confirmed(&self, _header: &BlockHeader, txdata: &TransactionData, height: u32) {
+        let mut confirmed = self.confirmed.lock().unwrap();
+        for (_pos, tx) in txdata.iter() {
+            confirmed.push((height, tx.txid()));
+        }
+    }
+
+    fn transaction_unconfirmed(&self, _txid: &Txid) {}
+
+    fn best_block_updated(&self, _header: &BlockHeader, height: u32) {
+        *self.best_block.lock().unwrap() = Some(height);
+    }
+
+    fn get_relevant_txids(&self) -> Vec<Txid> {
+        vec![]
+    }
+}
+
+fn setup() -> (BitcoinD, ElectrsD, TestWallet) {
+    let bitcoind_exe = bitcoind::downloaded_exe_path().expect("bitcoind executable");
+    let bitcoind = BitcoinD::new(bitcoind_exe).unwrap();
+    let electrs_exe = electrsd::downloaded_exe_path().expect("electrs executable");
+    let electrsd = ElectrsD::new(electrs_exe, &bitcoind).unwrap();
+
+    let client = Client::new(&electrsd.electrum_url).unwrap();
+    let descriptor = format!("wpkh({}/84'/1'/0'/0/*)", TEST_XPRV);
+    let wallet = Wallet::new(
+        &descriptor,
+        None,
+        Network::Regtest,
+        MemoryDatabase::default(),
+        ElectrumBlockchain::from(client),
+    )
+    .unwrap();
+
+    (bitcoind, electrsd, LightningWallet::new(wallet))
+}
+
+/// mines blocks to bitcoind's wallet and waits for electrs to index them
+fn mine(bitcoind: &BitcoinD, electrsd: &ElectrsD, wallet: &TestWallet, blocks: u64) -> u32 {
+    let miner = bitcoind.client.get_new_address(None, None).unwrap();
+    bitcoind.client.generate_to_address(blocks, &miner).unwrap();
+    let height = bitcoind.client.get_block_count().unwrap() as u32;
+
+    let started_at = Instant::now();
+    loop {
+        electrsd.trigger().unwrap();
+        if matches!(wallet.get_tip(), Ok((tip, _)) if tip == height) {
+            return height;
+        }
+        assert!(
+            started_at.elapsed() < Duration::from_secs(10),
+            "electrs did not catch up"
+        );
+        sleep(Duration::from_millis(100));
+    }
+}
+
+/// a p2wsh 2-of-2 multisig like a channel funding output
+fn funding_script() -> Script {
+    let secp = Secp256k1::new();
+    let keys = [[1u8; 32], [2u8; 32]]
+        .iter()
+        .map(|secret| {
+            let secret_key = SecretKey::from_slice(secret).unwrap();
+            PrivateKey::new(secret_key, Network::Regtest).public_key(&secp)
+        })
+        .collect::<Vec<_>>();
+
+    Builder::new()
+        .push_int(2)
+        .push_key(&keys[0])
+        .push_key(&keys[1])
+        .push_int(2)
+        .push_opcode(OP_CHECKMULTISIG)
+        .into_script()
+        .to_v0_p2wsh()
+}
+
+#[test]
+fn funding_transaction_confirms_through_sync() {
+    let (bitcoind, electrsd, wallet) = setup();
+    mine(&bitcoind, &electrsd, &wallet, 101);
+
+    let address = wallet.get_unused_address().unwrap();
+    bitcoind
+        .client
+        .send_to_address(
+            &address,
+            Amount::from_sat(1_000_000),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+    mine(&bitcoind, &electrsd, &wallet, 1);
+    wallet.sync_onchain_wallet().unwrap();
+    assert_eq!(wallet.get_balance().unwrap(), 1_000_000);
+
+    let output_script = funding_script();
+    let tx = wallet
+        .construct_funding_transaction_with_feerate(
+            &output_script,
+            500_000,
+            FeeRate::from_sat_per_vb(2.0),
+        )
+        .unwrap();
+    wallet.register_tx(&tx.txid(), &output_script);
+    wallet.broadcast_transaction(&tx);
+    let height = mine(&bitcoind, &electrsd, &wallet, 1);
+
+    let channel_manager = Arc::new(RecordingConfirm::default());
+    let chain_monitor = Arc::new(RecordingConfirm::default());
+    wallet
+        .sync(channel_manager.clone(), chain_monitor.clone())
+        .unwrap();
+
+    assert_eq!(
+        *channel_manager.confirmed.lock().unwrap(),
+        vec![(height, tx.txid())]
+    );
+    assert_eq!(*chain_monitor.best_block.lock().unwrap(), Some(height));
+}